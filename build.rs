@@ -1,5 +1,6 @@
 //! This build script copies the `memory.x` file from the crate root into
-//! a directory where the linker can always find it at build time.
+//! a directory where the linker can always find it at build time, limiting
+//! the firmware image to the flash below the storage region.
 //! For many projects this is optional, as the linker always searches the
 //! project root directory -- wherever `Cargo.toml` is. However, if you
 //! are using a workspace or have a more complicated build setup, this
@@ -9,6 +10,9 @@
 //! new memory settings.
 //!
 //! The build script also sets the linker flags to tell it which link script to use.
+//!
+//! The flash size and the offset of the storage region can be set per board with the
+//! `LILY58_FLASH_SIZE` and `LILY58_STORAGE_OFFSET` environment variables.
 
 use const_gen::*;
 use std::fs::File;
@@ -22,12 +26,21 @@ fn main() {
     println!("cargo:rerun-if-changed=vial.json");
     generate_vial_config();
 
+    println!("cargo:rerun-if-env-changed=LILY58_FLASH_SIZE");
+    println!("cargo:rerun-if-env-changed=LILY58_STORAGE_OFFSET");
+    let firmware_end = generate_flash_layout();
+
     // Put `memory.x` in our output directory and ensure it's
-    // on the linker search path.
+    // on the linker search path. The firmware's flash ends where
+    // the macro sector below storage begins, so the linker fails if
+    // the two would overlap.
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let memory_x = fs::read_to_string("memory.x")
+        .unwrap()
+        .replace("FIRMWARE_END", &format!("{:#x}", firmware_end));
     File::create(out.join("memory.x"))
         .unwrap()
-        .write_all(include_bytes!("memory.x"))
+        .write_all(memory_x.as_bytes())
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
@@ -77,3 +90,48 @@ fn generate_vial_config() {
     .join("\n");
     fs::write(out_file, const_declarations).unwrap();
 }
+
+// Sector size of the RP2040's external flash
+const SECTOR_SIZE: usize = 4096;
+// One sector for board settings followed by RMK's storage
+const STORAGE_SECTORS: usize = 3;
+// The second stage bootloader sits at the start of flash
+const BOOT2_SIZE: usize = 0x100;
+
+fn env_size(name: &str) -> Option<usize> {
+    let value = env::var(name).ok()?;
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    Some(parsed.unwrap_or_else(|_| panic!("{} is not a valid size: {}", name, value)))
+}
+
+fn generate_flash_layout() -> usize {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("flash_layout.rs");
+
+    let flash_size = env_size("LILY58_FLASH_SIZE").unwrap_or(2 * 1024 * 1024);
+    let storage_offset =
+        env_size("LILY58_STORAGE_OFFSET").unwrap_or(flash_size - STORAGE_SECTORS * SECTOR_SIZE);
+    assert!(
+        storage_offset % SECTOR_SIZE == 0,
+        "LILY58_STORAGE_OFFSET must be a multiple of the {} byte sector size",
+        SECTOR_SIZE
+    );
+    // Dynamic macros are kept in the sector below the storage region, where the firmware ends
+    let macro_offset = storage_offset.saturating_sub(SECTOR_SIZE);
+    assert!(
+        macro_offset > BOOT2_SIZE && storage_offset + STORAGE_SECTORS * SECTOR_SIZE <= flash_size,
+        "Storage at {:#x} doesn't fit in {:#x} bytes of flash",
+        storage_offset,
+        flash_size
+    );
+
+    let const_declarations = [
+        const_declaration!(pub FLASH_SIZE = flash_size),
+        const_declaration!(pub STORAGE_OFFSET = storage_offset),
+    ]
+    .join("\n");
+    fs::write(out_file, const_declarations).unwrap();
+    macro_offset
+}
@@ -0,0 +1,225 @@
+//! Board settings that RMK's storage has no record for, kept in a flash sector of their own.
+
+use crate::debounce::DebounceConfig;
+use crate::layout::LayoutFingerprint;
+
+const CONFIG_MAGIC: [u8; 4] = *b"L58C";
+const CONFIG_VERSION: u8 = 2;
+pub const CONFIG_SIZE: usize = 16;
+
+/// The flash sector holding the settings, with NOR flash semantics: writes can only clear bits,
+/// so the sector is erased before every write.
+pub trait ConfigSector {
+    type Error;
+
+    fn read(&mut self, bytes: &mut [u8]) -> Result<(), Self::Error>;
+    fn erase(&mut self) -> Result<(), Self::Error>;
+    /// Writes from the start of the sector.
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoardConfig {
+    pub debounce: DebounceConfig,
+    /// Layout RMK's storage was last cleared or written for, only tracked on the central
+    pub layout: Option<LayoutFingerprint>,
+}
+
+impl BoardConfig {
+    pub fn to_bytes(self) -> [u8; CONFIG_SIZE] {
+        let mut bytes = [0xFF; CONFIG_SIZE];
+        bytes[..4].copy_from_slice(&CONFIG_MAGIC);
+        bytes[4] = CONFIG_VERSION;
+        bytes[5..7].copy_from_slice(&self.debounce.to_bits().to_le_bytes());
+        if let Some(layout) = self.layout {
+            bytes[7] = 1;
+            bytes[8..15].copy_from_slice(&layout.to_bytes());
+        }
+        bytes
+    }
+
+    // Older versions are migrated by keeping the fields they already had
+    pub fn from_bytes(bytes: &[u8; CONFIG_SIZE]) -> Option<Self> {
+        if bytes[..4] != CONFIG_MAGIC {
+            return None;
+        }
+        let debounce = DebounceConfig::from_bits(u16::from_le_bytes([bytes[5], bytes[6]]))?;
+        let layout = match bytes[4] {
+            1 => None,
+            CONFIG_VERSION if bytes[7] == 1 => Some(LayoutFingerprint::from_bytes(
+                bytes[8..15].try_into().unwrap(),
+            )),
+            CONFIG_VERSION => None,
+            _ => return None,
+        };
+        Some(Self { debounce, layout })
+    }
+}
+
+/// Reads the saved settings, `None` if there are none or they can't be read.
+pub fn read<S: ConfigSector>(sector: &mut S) -> Option<BoardConfig> {
+    let mut bytes = [0; CONFIG_SIZE];
+    sector.read(&mut bytes).ok()?;
+    BoardConfig::from_bytes(&bytes)
+}
+
+/// Saves the settings, unless they're already saved.
+pub fn store<S: ConfigSector>(sector: &mut S, config: BoardConfig) -> Result<(), S::Error> {
+    if read(sector) == Some(config) {
+        return Ok(());
+    }
+    sector.erase()?;
+    sector.write(&config.to_bytes())
+}
+
+/// Changes the saved settings, starting from `default` if there are none.
+pub fn update<S: ConfigSector>(
+    sector: &mut S,
+    default: BoardConfig,
+    f: impl FnOnce(&mut BoardConfig),
+) -> Result<(), S::Error> {
+    let mut config = read(sector).unwrap_or(default);
+    f(&mut config);
+    store(sector, config)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::debounce::DebounceAlgorithm;
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub(crate) struct PowerLoss;
+
+    /// A flash sector that loses power after a number of erases and writes.
+    pub(crate) struct MemSector {
+        pub bytes: [u8; 64],
+        pub power: Option<usize>,
+    }
+
+    impl MemSector {
+        pub(crate) fn new() -> Self {
+            Self {
+                bytes: [0xFF; 64],
+                power: None,
+            }
+        }
+
+        fn use_power(&mut self) -> Result<(), PowerLoss> {
+            match &mut self.power {
+                Some(0) => Err(PowerLoss),
+                Some(left) => {
+                    *left -= 1;
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl ConfigSector for MemSector {
+        type Error = PowerLoss;
+
+        fn read(&mut self, bytes: &mut [u8]) -> Result<(), PowerLoss> {
+            bytes.copy_from_slice(&self.bytes[..bytes.len()]);
+            Ok(())
+        }
+
+        fn erase(&mut self) -> Result<(), PowerLoss> {
+            self.use_power()?;
+            self.bytes = [0xFF; 64];
+            Ok(())
+        }
+
+        fn write(&mut self, bytes: &[u8]) -> Result<(), PowerLoss> {
+            self.use_power()?;
+            for (cell, byte) in self.bytes.iter_mut().zip(bytes) {
+                *cell &= byte;
+            }
+            Ok(())
+        }
+    }
+
+    pub(crate) const DEFAULT: BoardConfig = BoardConfig {
+        debounce: DebounceConfig {
+            algorithm: DebounceAlgorithm::DeferPerKey,
+            time_ms: 5,
+        },
+        layout: None,
+    };
+
+    const LAYOUT: LayoutFingerprint = LayoutFingerprint {
+        layers: 6,
+        rows: 10,
+        cols: 6,
+        keymap_hash: 0x1234_5678,
+    };
+
+    #[test]
+    fn round_trips() {
+        let config = BoardConfig {
+            debounce: DebounceConfig {
+                algorithm: DebounceAlgorithm::EagerPerKey,
+                time_ms: 12,
+            },
+            layout: Some(LAYOUT),
+        };
+        assert_eq!(BoardConfig::from_bytes(&config.to_bytes()), Some(config));
+        assert_eq!(BoardConfig::from_bytes(&DEFAULT.to_bytes()), Some(DEFAULT));
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        let mut bytes = [0xFF; CONFIG_SIZE];
+        bytes[..4].copy_from_slice(b"L58C");
+        bytes[4] = 1;
+        bytes[5..7].copy_from_slice(&0x0107u16.to_le_bytes());
+        let config = BoardConfig::from_bytes(&bytes).unwrap();
+        assert_eq!(config.debounce.algorithm, DebounceAlgorithm::EagerPerKey);
+        assert_eq!(config.debounce.time_ms, 7);
+        assert_eq!(config.layout, None);
+    }
+
+    #[test]
+    fn rejects_unknown_data() {
+        assert_eq!(BoardConfig::from_bytes(&[0xFF; CONFIG_SIZE]), None);
+        let mut bytes = DEFAULT.to_bytes();
+        bytes[4] = 3;
+        assert_eq!(BoardConfig::from_bytes(&bytes), None);
+    }
+
+    #[test]
+    fn store_reads_back() {
+        let mut sector = MemSector::new();
+        assert_eq!(read(&mut sector), None);
+        update(&mut sector, DEFAULT, |config| config.debounce.time_ms = 9).unwrap();
+        update(&mut sector, DEFAULT, |config| config.layout = Some(LAYOUT)).unwrap();
+        let config = read(&mut sector).unwrap();
+        assert_eq!(config.debounce.time_ms, 9);
+        assert_eq!(config.layout, Some(LAYOUT));
+    }
+
+    #[test]
+    fn store_skips_unchanged_settings() {
+        let mut sector = MemSector::new();
+        store(&mut sector, DEFAULT).unwrap();
+        sector.power = Some(0);
+        assert_eq!(store(&mut sector, DEFAULT), Ok(()));
+    }
+
+    #[test]
+    fn power_loss_while_storing_never_leaves_bad_settings() {
+        let config = BoardConfig {
+            layout: Some(LAYOUT),
+            ..DEFAULT
+        };
+        for power in 0..2 {
+            let mut sector = MemSector::new();
+            store(&mut sector, DEFAULT).unwrap();
+            sector.power = Some(power);
+            assert_eq!(store(&mut sector, config), Err(PowerLoss));
+            // Either the old settings or none at all
+            assert!(matches!(read(&mut sector), Some(DEFAULT) | None));
+        }
+    }
+}
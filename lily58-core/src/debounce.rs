@@ -0,0 +1,335 @@
+//! Debounce algorithms for the key matrix, switchable while it's running.
+
+pub const MAX_DEBOUNCE_MS: u8 = 63;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebounceAlgorithm {
+    /// Report a change once the key has held its new state for the debounce time.
+    DeferPerKey,
+    /// Report a change immediately, then ignore the key for the debounce time.
+    EagerPerKey,
+    /// Report changes once no key in the row has changed for the debounce time.
+    DeferPerRow,
+    /// Report changes once no key at all has changed for the debounce time.
+    DeferGlobal,
+}
+
+impl DebounceAlgorithm {
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(DebounceAlgorithm::DeferPerKey),
+            1 => Some(DebounceAlgorithm::EagerPerKey),
+            2 => Some(DebounceAlgorithm::DeferPerRow),
+            3 => Some(DebounceAlgorithm::DeferGlobal),
+            _ => None,
+        }
+    }
+
+    pub const fn next(self) -> Self {
+        match self {
+            DebounceAlgorithm::DeferPerKey => DebounceAlgorithm::EagerPerKey,
+            DebounceAlgorithm::EagerPerKey => DebounceAlgorithm::DeferPerRow,
+            DebounceAlgorithm::DeferPerRow => DebounceAlgorithm::DeferGlobal,
+            DebounceAlgorithm::DeferGlobal => DebounceAlgorithm::DeferPerKey,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DebounceConfig {
+    pub algorithm: DebounceAlgorithm,
+    pub time_ms: u8,
+}
+
+impl DebounceConfig {
+    pub const fn to_bits(self) -> u16 {
+        ((self.algorithm as u16) << 8) | self.time_ms as u16
+    }
+
+    pub const fn from_bits(bits: u16) -> Option<Self> {
+        let time_ms = bits as u8;
+        match DebounceAlgorithm::from_u8((bits >> 8) as u8) {
+            Some(algorithm) if time_ms <= MAX_DEBOUNCE_MS => {
+                Some(DebounceConfig { algorithm, time_ms })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of debouncing one key in one scan, as in RMK's `DebounceState`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebounceState {
+    /// The pin matches the key's state
+    Ignored,
+    /// The pin differs, but hasn't settled yet
+    InProgress,
+    /// The key's state should change to the pin's
+    Debounced,
+}
+
+/// Timers of every algorithm, kept up to date whichever is in use so switching doesn't lose
+/// track of keys that are bouncing.
+pub struct Debouncer<const INPUT_PIN_NUM: usize, const OUTPUT_PIN_NUM: usize> {
+    // Pin states seen in the previous scan, used to restart the timers below on every bounce
+    raw: [[bool; OUTPUT_PIN_NUM]; INPUT_PIN_NUM],
+    // Time in milliseconds of the last raw change for each key, row, and the whole matrix
+    key_changed_at: [[u32; OUTPUT_PIN_NUM]; INPUT_PIN_NUM],
+    row_changed_at: [u32; INPUT_PIN_NUM],
+    changed_at: u32,
+    // Time in milliseconds at which each key last reported a change, for eager debouncing
+    reported_at: [[Option<u32>; OUTPUT_PIN_NUM]; INPUT_PIN_NUM],
+}
+
+impl<const INPUT_PIN_NUM: usize, const OUTPUT_PIN_NUM: usize> Default
+    for Debouncer<INPUT_PIN_NUM, OUTPUT_PIN_NUM>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const INPUT_PIN_NUM: usize, const OUTPUT_PIN_NUM: usize>
+    Debouncer<INPUT_PIN_NUM, OUTPUT_PIN_NUM>
+{
+    pub const fn new() -> Self {
+        Self {
+            raw: [[false; OUTPUT_PIN_NUM]; INPUT_PIN_NUM],
+            key_changed_at: [[0; OUTPUT_PIN_NUM]; INPUT_PIN_NUM],
+            row_changed_at: [0; INPUT_PIN_NUM],
+            changed_at: 0,
+            reported_at: [[None; OUTPUT_PIN_NUM]; INPUT_PIN_NUM],
+        }
+    }
+
+    /// Debounces the key at `in_idx`, `out_idx`, whose pin reads `pin_state` at `now`, in
+    /// milliseconds of a wrapping clock, while it's reported as `pressed`. Has to be called for
+    /// every key on every scan, for the per row and global timers to see all changes.
+    pub fn update(
+        &mut self,
+        in_idx: usize,
+        out_idx: usize,
+        pin_state: bool,
+        pressed: bool,
+        config: DebounceConfig,
+        now: u32,
+    ) -> DebounceState {
+        if self.raw[in_idx][out_idx] != pin_state {
+            self.raw[in_idx][out_idx] = pin_state;
+            self.key_changed_at[in_idx][out_idx] = now;
+            self.row_changed_at[in_idx] = now;
+            self.changed_at = now;
+        }
+        if pin_state == pressed {
+            return DebounceState::Ignored;
+        }
+
+        let settled = |changed_at: u32| now.wrapping_sub(changed_at) >= config.time_ms as u32;
+        let debounced = match config.algorithm {
+            DebounceAlgorithm::DeferPerKey => settled(self.key_changed_at[in_idx][out_idx]),
+            DebounceAlgorithm::DeferPerRow => settled(self.row_changed_at[in_idx]),
+            DebounceAlgorithm::DeferGlobal => settled(self.changed_at),
+            DebounceAlgorithm::EagerPerKey => {
+                let reported_at = &mut self.reported_at[in_idx][out_idx];
+                let ready = reported_at.is_none_or(settled);
+                if ready {
+                    *reported_at = Some(now);
+                }
+                ready
+            }
+        };
+        if debounced {
+            DebounceState::Debounced
+        } else {
+            DebounceState::InProgress
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIME_MS: u8 = 5;
+    // Keys 0 and 1 share a row, key 2 is on the other one
+    const KEYS: [(usize, usize); 3] = [(0, 0), (0, 1), (1, 0)];
+
+    /// Times in milliseconds at which the pin of a key changes, starting released.
+    type Waveform = &'static [u32];
+
+    fn pin_at(waveform: Waveform, t: u32) -> bool {
+        waveform.iter().filter(|&&edge| edge <= t).count() % 2 == 1
+    }
+
+    /// Scans the matrix every millisecond from `start` like RMK does, and returns the time
+    /// relative to `start`, key and state of each reported change.
+    fn replay(
+        algorithm: DebounceAlgorithm,
+        waveforms: [Waveform; 3],
+        start: u32,
+    ) -> Vec<(u32, usize, bool)> {
+        let config = DebounceConfig {
+            algorithm,
+            time_ms: TIME_MS,
+        };
+        let mut debouncer = Debouncer::<2, 2>::new();
+        let mut pressed = [false; 3];
+        let mut reports = Vec::new();
+        for t in 0..200 {
+            for (key, &(in_idx, out_idx)) in KEYS.iter().enumerate() {
+                let pin = pin_at(waveforms[key], t);
+                let now = start.wrapping_add(t);
+                if debouncer.update(in_idx, out_idx, pin, pressed[key], config, now)
+                    == DebounceState::Debounced
+                {
+                    pressed[key] = pin;
+                    reports.push((t, key, pin));
+                }
+            }
+        }
+        reports
+    }
+
+    const ALGORITHMS: [DebounceAlgorithm; 4] = [
+        DebounceAlgorithm::DeferPerKey,
+        DebounceAlgorithm::EagerPerKey,
+        DebounceAlgorithm::DeferPerRow,
+        DebounceAlgorithm::DeferGlobal,
+    ];
+
+    // Contacts bounce for 3ms after closing and 2ms after opening
+    const BOUNCY: Waveform = &[10, 11, 12, 13, 14, 60, 61, 62];
+
+    #[test]
+    fn config_bits_round_trip() {
+        for algorithm in ALGORITHMS {
+            let config = DebounceConfig {
+                algorithm,
+                time_ms: MAX_DEBOUNCE_MS,
+            };
+            assert_eq!(DebounceConfig::from_bits(config.to_bits()), Some(config));
+        }
+        assert_eq!(DebounceConfig::from_bits(0x0400), None);
+        assert_eq!(DebounceConfig::from_bits(MAX_DEBOUNCE_MS as u16 + 1), None);
+    }
+
+    #[test]
+    fn next_cycles_through_every_algorithm() {
+        let mut algorithm = DebounceAlgorithm::DeferPerKey;
+        for expected in ALGORITHMS.iter().cycle().skip(1).take(4) {
+            algorithm = algorithm.next();
+            assert_eq!(algorithm, *expected);
+        }
+    }
+
+    #[test]
+    fn clean_press() {
+        for algorithm in ALGORITHMS {
+            let latency = match algorithm {
+                DebounceAlgorithm::EagerPerKey => 0,
+                _ => TIME_MS as u32,
+            };
+            assert_eq!(
+                replay(algorithm, [&[10, 50], &[], &[]], 0),
+                [(10 + latency, 0, true), (50 + latency, 0, false)],
+                "{algorithm:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn bouncy_press_reports_once() {
+        for algorithm in ALGORITHMS {
+            let expected = match algorithm {
+                // On the first edge, the bounces after it fall inside the window
+                DebounceAlgorithm::EagerPerKey => [(10, 0, true), (60, 0, false)],
+                // Once the last bounce has settled
+                _ => [(19, 0, true), (67, 0, false)],
+            };
+            assert_eq!(
+                replay(algorithm, [BOUNCY, &[], &[]], 0),
+                expected,
+                "{algorithm:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn eager_ignores_the_key_inside_the_window() {
+        // Released again 2ms after the press is reported, still inside the 5ms window: the
+        // release is only reported once the window is over
+        assert_eq!(
+            replay(DebounceAlgorithm::EagerPerKey, [&[10, 12], &[], &[]], 0),
+            [(10, 0, true), (15, 0, false)]
+        );
+        // A spike is a keypress for eager debouncing, but never settles for deferred
+        assert_eq!(
+            replay(DebounceAlgorithm::DeferPerKey, [&[10, 12], &[], &[]], 0),
+            []
+        );
+    }
+
+    #[test]
+    fn bouncing_key_delays_its_row_or_matrix() {
+        // Key 1 and 2 are pressed cleanly while key 0 is bouncing
+        let waveforms: [Waveform; 3] = [BOUNCY, &[12], &[12]];
+        let reports = |algorithm| {
+            let mut reports = replay(algorithm, waveforms, 0);
+            reports.retain(|&(t, _, pressed)| pressed && t < 50);
+            reports.sort_by_key(|&(t, key, _)| (key, t));
+            reports
+        };
+        assert_eq!(
+            reports(DebounceAlgorithm::DeferPerKey),
+            [(19, 0, true), (17, 1, true), (17, 2, true)]
+        );
+        assert_eq!(
+            reports(DebounceAlgorithm::EagerPerKey),
+            [(10, 0, true), (12, 1, true), (12, 2, true)]
+        );
+        assert_eq!(
+            reports(DebounceAlgorithm::DeferPerRow),
+            [(19, 0, true), (19, 1, true), (17, 2, true)]
+        );
+        assert_eq!(
+            reports(DebounceAlgorithm::DeferGlobal),
+            [(19, 0, true), (19, 1, true), (19, 2, true)]
+        );
+    }
+
+    #[test]
+    fn works_across_clock_wrap() {
+        for algorithm in ALGORITHMS {
+            assert_eq!(
+                replay(algorithm, [BOUNCY, &[], &[]], u32::MAX - 40),
+                replay(algorithm, [BOUNCY, &[], &[]], 0),
+                "{algorithm:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn switching_algorithm_keeps_timers() {
+        let mut debouncer = Debouncer::<1, 1>::new();
+        let defer = DebounceConfig {
+            algorithm: DebounceAlgorithm::DeferPerKey,
+            time_ms: TIME_MS,
+        };
+        let global = DebounceConfig {
+            algorithm: DebounceAlgorithm::DeferGlobal,
+            ..defer
+        };
+        assert_eq!(
+            debouncer.update(0, 0, true, false, defer, 10),
+            DebounceState::InProgress
+        );
+        assert_eq!(
+            debouncer.update(0, 0, true, false, global, 14),
+            DebounceState::InProgress
+        );
+        assert_eq!(
+            debouncer.update(0, 0, true, false, global, 15),
+            DebounceState::Debounced
+        );
+    }
+}
@@ -0,0 +1,8 @@
+//! Bits of the HID keyboard report, for keys typed outside of RMK's keymap.
+
+pub const MOD_NONE: u8 = 0x00;
+pub const MOD_CTRL: u8 = 0x01;
+pub const MOD_SHIFT: u8 = 0x02;
+pub const MOD_ALT: u8 = 0x04;
+/// Right Alt, which is AltGr on most layouts outside the US
+pub const MOD_ALTGR: u8 = 0x40;
@@ -0,0 +1,221 @@
+//! Versioning of RMK's storage by the keymap layout it was written for.
+
+use crate::config::{self, BoardConfig, ConfigSector};
+
+/// Identifies the keymap layout RMK's storage was written for. Stored keymaps are only valid for
+/// the layout they were saved with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayoutFingerprint {
+    pub layers: u8,
+    pub rows: u8,
+    pub cols: u8,
+    pub keymap_hash: u32,
+}
+
+impl LayoutFingerprint {
+    pub const SIZE: usize = 7;
+
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let hash = self.keymap_hash.to_le_bytes();
+        [
+            self.layers,
+            self.rows,
+            self.cols,
+            hash[0],
+            hash[1],
+            hash[2],
+            hash[3],
+        ]
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            layers: bytes[0],
+            rows: bytes[1],
+            cols: bytes[2],
+            keymap_hash: u32::from_le_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]),
+        }
+    }
+}
+
+/// 32 bit FNV-1a, for hashing the default keymap.
+pub struct Fnv1a(u32);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fnv1a {
+    pub const fn new() -> Self {
+        Self(0x811c_9dc5)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u32).wrapping_mul(0x0100_0193);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Result of comparing the layout RMK's storage was written for with the current one.
+///
+/// The new fingerprint is only saved by `record`, once the storage has been cleared. Saving it
+/// first would let a power loss in between leave the old keymap behind a matching fingerprint.
+#[must_use = "the layout has to be recorded once the storage is cleared"]
+pub struct LayoutCheck {
+    current: LayoutFingerprint,
+    changed: bool,
+}
+
+impl LayoutCheck {
+    /// Compares against the saved settings, without writing anything.
+    pub fn new(saved: Option<BoardConfig>, current: LayoutFingerprint) -> Self {
+        let changed = saved.and_then(|config| config.layout) != Some(current);
+        Self { current, changed }
+    }
+
+    /// Whether RMK's storage was written for a different layout and has to be cleared.
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Saves the current layout, once RMK's storage is cleared or known to match it. Board
+    /// settings that don't depend on the layout are kept.
+    pub fn record<S: ConfigSector>(
+        self,
+        sector: &mut S,
+        default: BoardConfig,
+    ) -> Result<(), S::Error> {
+        config::update(sector, default, |config| config.layout = Some(self.current))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{MemSector, PowerLoss, DEFAULT};
+
+    const OLD: LayoutFingerprint = LayoutFingerprint {
+        layers: 5,
+        rows: 10,
+        cols: 6,
+        keymap_hash: 1,
+    };
+    const NEW: LayoutFingerprint = LayoutFingerprint { layers: 6, ..OLD };
+
+    /// A board whose RMK storage holds a keymap saved for some layout.
+    struct Board {
+        sector: MemSector,
+        keymap_layout: LayoutFingerprint,
+    }
+
+    impl Board {
+        fn with_layout(layout: LayoutFingerprint) -> Self {
+            let mut sector = MemSector::new();
+            config::store(
+                &mut sector,
+                BoardConfig {
+                    layout: Some(layout),
+                    ..DEFAULT
+                },
+            )
+            .unwrap();
+            Self {
+                sector,
+                keymap_layout: layout,
+            }
+        }
+
+        // Boots like the central does, losing power after `power` flash operations. Clearing
+        // RMK's storage counts as one.
+        fn boot(
+            &mut self,
+            current: LayoutFingerprint,
+            power: Option<usize>,
+        ) -> Result<(), PowerLoss> {
+            self.sector.power = power;
+            let check = LayoutCheck::new(config::read(&mut self.sector), current);
+            if check.changed() {
+                if self.sector.power == Some(0) {
+                    return Err(PowerLoss);
+                }
+                self.sector.power = self.sector.power.map(|power| power - 1);
+                self.keymap_layout = current;
+            }
+            let recorded = check.record(&mut self.sector, DEFAULT);
+            self.sector.power = None;
+            recorded
+        }
+    }
+
+    #[test]
+    fn hashes_like_fnv1a() {
+        let mut hasher = Fnv1a::new();
+        assert_eq!(hasher.finish(), 0x811c_9dc5);
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xe40c_292c);
+    }
+
+    #[test]
+    fn fingerprint_round_trips() {
+        let fingerprint = LayoutFingerprint {
+            keymap_hash: 0xDEAD_BEEF,
+            ..NEW
+        };
+        assert_eq!(
+            LayoutFingerprint::from_bytes(&fingerprint.to_bytes()),
+            fingerprint
+        );
+    }
+
+    #[test]
+    fn clears_on_first_boot_and_layout_change_only() {
+        assert!(LayoutCheck::new(None, NEW).changed());
+        let mut board = Board::with_layout(OLD);
+        assert!(LayoutCheck::new(config::read(&mut board.sector), NEW).changed());
+        assert!(!LayoutCheck::new(config::read(&mut board.sector), OLD).changed());
+    }
+
+    #[test]
+    fn checking_writes_nothing() {
+        let mut board = Board::with_layout(OLD);
+        let before = board.sector.bytes;
+        let _ = LayoutCheck::new(config::read(&mut board.sector), NEW);
+        assert_eq!(board.sector.bytes, before);
+    }
+
+    #[test]
+    fn recording_keeps_other_settings() {
+        let mut board = Board::with_layout(OLD);
+        config::update(&mut board.sector, DEFAULT, |config| {
+            config.debounce.time_ms = 11
+        })
+        .unwrap();
+        board.boot(NEW, None).unwrap();
+        let config = config::read(&mut board.sector).unwrap();
+        assert_eq!(config.layout, Some(NEW));
+        assert_eq!(config.debounce.time_ms, 11);
+    }
+
+    #[test]
+    fn power_loss_during_upgrade_never_keeps_the_old_keymap() {
+        // Lose power at every step of the first boot after an upgrade: clearing the storage,
+        // erasing the config sector and writing it
+        for power in 0..3 {
+            let mut board = Board::with_layout(OLD);
+            assert_eq!(board.boot(NEW, Some(power)), Err(PowerLoss));
+            board.boot(NEW, None).unwrap();
+            assert_eq!(board.keymap_layout, NEW, "power lost after {power} steps");
+            assert!(
+                !LayoutCheck::new(config::read(&mut board.sector), NEW).changed(),
+                "power lost after {power} steps"
+            );
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Matching of the keys typed after the leader key against the configured sequences.
+
+/// Longest sequence that can follow the leader key.
+pub const MAX_SEQUENCE_LEN: usize = 4;
+
+/// Keys typed after the leader key, by the letters and digits on them, and what they do.
+pub struct LeaderSequence<A> {
+    keys: &'static [u8],
+    action: A,
+}
+
+impl<A> LeaderSequence<A> {
+    pub const fn new(keys: &'static [u8], action: A) -> Self {
+        Self { keys, action }
+    }
+}
+
+/// The lowercase letter or digit on a key, by its HID usage, as used in `LeaderSequence`s.
+pub const fn key_char(usage: u16) -> Option<u8> {
+    match usage {
+        0x04..=0x1D => Some(b'a' + (usage - 0x04) as u8),
+        0x1E..=0x26 => Some(b'1' + (usage - 0x1E) as u8),
+        0x27 => Some(b'0'),
+        _ => None,
+    }
+}
+
+const fn is_prefix(prefix: &[u8], keys: &[u8]) -> bool {
+    if prefix.len() > keys.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < prefix.len() {
+        if prefix[i] != keys[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Fails the build if a sequence could never fire, because it's empty, too long, has a key that
+/// isn't a letter or digit or starts with another sequence.
+pub const fn validate_leader_sequences<A>(sequences: &[LeaderSequence<A>]) {
+    let mut i = 0;
+    while i < sequences.len() {
+        let keys = sequences[i].keys;
+        assert!(!keys.is_empty(), "Leader sequences can't be empty");
+        assert!(
+            keys.len() <= MAX_SEQUENCE_LEN,
+            "Leader sequence is too long"
+        );
+        let mut k = 0;
+        while k < keys.len() {
+            assert!(
+                keys[k].is_ascii_lowercase() || keys[k].is_ascii_digit(),
+                "Leader sequences can only have lowercase letters and digits"
+            );
+            k += 1;
+        }
+        let mut j = 0;
+        while j < sequences.len() {
+            assert!(
+                i == j || !is_prefix(keys, sequences[j].keys),
+                "Leader sequence is a prefix of another one"
+            );
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequenceMatch<A> {
+    Complete(A),
+    /// More keys could still complete a sequence
+    Partial,
+    None,
+}
+
+/// Looks up the keys typed so far.
+pub fn match_sequence<A: Copy>(sequences: &[LeaderSequence<A>], keys: &[u8]) -> SequenceMatch<A> {
+    let mut result = SequenceMatch::None;
+    for sequence in sequences {
+        if sequence.keys == keys {
+            return SequenceMatch::Complete(sequence.action);
+        }
+        if is_prefix(keys, sequence.keys) {
+            result = SequenceMatch::Partial;
+        }
+    }
+    result
+}
+
+/// What to do after a key typed on the leader layer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step<A> {
+    /// Wait for the next key
+    Pending,
+    /// Leave the leader layer and run the action
+    Run(A),
+    /// Leave the leader layer without doing anything
+    Cancel,
+}
+
+/// Keys typed since the leader key was pressed, which have to complete a sequence within
+/// `timeout_ms` of it.
+pub struct Leader<A: 'static> {
+    sequences: &'static [LeaderSequence<A>],
+    started: u32,
+    timeout_ms: u32,
+    keys: [u8; MAX_SEQUENCE_LEN],
+    len: usize,
+}
+
+impl<A: Copy> Leader<A> {
+    /// Starts a sequence for the leader key pressed at `now`, in milliseconds of a wrapping clock.
+    pub fn start(sequences: &'static [LeaderSequence<A>], timeout_ms: u32, now: u32) -> Self {
+        Self {
+            sequences,
+            started: now,
+            timeout_ms,
+            keys: [0; MAX_SEQUENCE_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn timed_out(&self, now: u32) -> bool {
+        now.wrapping_sub(self.started) >= self.timeout_ms
+    }
+
+    /// Adds a key pressed at `now`, by its `key_char`, `None` for keys without one.
+    pub fn key(&mut self, key: Option<u8>, now: u32) -> Step<A> {
+        if self.timed_out(now) || self.len == MAX_SEQUENCE_LEN {
+            return Step::Cancel;
+        }
+        let Some(key) = key else {
+            return Step::Cancel;
+        };
+        self.keys[self.len] = key;
+        self.len += 1;
+        match match_sequence(self.sequences, &self.keys[..self.len]) {
+            SequenceMatch::Complete(action) => Step::Run(action),
+            SequenceMatch::Partial => Step::Pending,
+            SequenceMatch::None => Step::Cancel,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: u32 = 1000;
+    const SEQUENCES: &[LeaderSequence<u8>] = &[
+        LeaderSequence::new(b"q", 1),
+        LeaderSequence::new(b"td", 2),
+        LeaderSequence::new(b"tf", 3),
+        LeaderSequence::new(b"abcd", 4),
+    ];
+    const _: () = validate_leader_sequences(SEQUENCES);
+
+    fn type_keys(keys: &[u8], start: u32, interval: u32) -> Vec<Step<u8>> {
+        let mut leader = Leader::start(SEQUENCES, TIMEOUT, start);
+        keys.iter()
+            .enumerate()
+            .map(|(i, &key)| leader.key(Some(key), start.wrapping_add(interval * (i as u32 + 1))))
+            .collect()
+    }
+
+    #[test]
+    fn key_chars() {
+        assert_eq!(key_char(0x04), Some(b'a'));
+        assert_eq!(key_char(0x1D), Some(b'z'));
+        assert_eq!(key_char(0x1E), Some(b'1'));
+        assert_eq!(key_char(0x26), Some(b'9'));
+        assert_eq!(key_char(0x27), Some(b'0'));
+        assert_eq!(key_char(0x28), None);
+        assert_eq!(key_char(0x00), None);
+    }
+
+    #[test]
+    fn prefixes() {
+        assert!(is_prefix(b"", b"td"));
+        assert!(is_prefix(b"t", b"td"));
+        assert!(is_prefix(b"td", b"td"));
+        assert!(!is_prefix(b"tf", b"td"));
+        assert!(!is_prefix(b"tdx", b"td"));
+    }
+
+    #[test]
+    fn matches() {
+        assert_eq!(match_sequence(SEQUENCES, b"q"), SequenceMatch::Complete(1));
+        assert_eq!(match_sequence(SEQUENCES, b"t"), SequenceMatch::Partial);
+        assert_eq!(match_sequence(SEQUENCES, b"tf"), SequenceMatch::Complete(3));
+        assert_eq!(match_sequence(SEQUENCES, b"tx"), SequenceMatch::None);
+        assert_eq!(match_sequence(SEQUENCES, b"x"), SequenceMatch::None);
+    }
+
+    #[test]
+    #[should_panic(expected = "prefix of another one")]
+    fn ambiguous_sequences_are_rejected() {
+        validate_leader_sequences(&[
+            LeaderSequence::new(b"s", ()),
+            LeaderSequence::new(b"sh", ()),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "prefix of another one")]
+    fn duplicate_sequences_are_rejected() {
+        validate_leader_sequences(&[LeaderSequence::new(b"s", ()), LeaderSequence::new(b"s", ())]);
+    }
+
+    #[test]
+    #[should_panic(expected = "too long")]
+    fn long_sequences_are_rejected() {
+        validate_leader_sequences(&[LeaderSequence::new(b"abcde", ())]);
+    }
+
+    #[test]
+    #[should_panic(expected = "lowercase letters and digits")]
+    fn uppercase_sequences_are_rejected() {
+        validate_leader_sequences(&[LeaderSequence::new(b"Q", ())]);
+    }
+
+    #[test]
+    fn runs_completed_sequences() {
+        assert_eq!(type_keys(b"q", 0, 100), [Step::Run(1)]);
+        assert_eq!(type_keys(b"td", 0, 100), [Step::Pending, Step::Run(2)]);
+        assert_eq!(
+            type_keys(b"abcd", 0, 100),
+            [Step::Pending, Step::Pending, Step::Pending, Step::Run(4)]
+        );
+    }
+
+    #[test]
+    fn cancels_on_keys_not_in_any_sequence() {
+        assert_eq!(type_keys(b"x", 0, 100), [Step::Cancel]);
+        assert_eq!(type_keys(b"tq", 0, 100), [Step::Pending, Step::Cancel]);
+
+        let mut leader = Leader::start(SEQUENCES, TIMEOUT, 0);
+        assert_eq!(leader.key(Some(b't'), 100), Step::Pending);
+        assert_eq!(leader.key(None, 200), Step::Cancel);
+    }
+
+    #[test]
+    fn times_out() {
+        // The whole sequence counts towards the timeout, not each key
+        assert_eq!(type_keys(b"td", 0, 499), [Step::Pending, Step::Run(2)]);
+        assert_eq!(type_keys(b"td", 0, 500), [Step::Pending, Step::Cancel]);
+        assert_eq!(type_keys(b"q", 0, TIMEOUT), [Step::Cancel]);
+
+        let leader = Leader::start(SEQUENCES, TIMEOUT, 0);
+        assert!(!leader.timed_out(TIMEOUT - 1));
+        assert!(leader.timed_out(TIMEOUT));
+    }
+
+    #[test]
+    fn times_out_across_clock_wrap() {
+        let start = u32::MAX - 100;
+        assert_eq!(type_keys(b"td", start, 400), [Step::Pending, Step::Run(2)]);
+        assert_eq!(type_keys(b"td", start, 600), [Step::Pending, Step::Cancel]);
+    }
+}
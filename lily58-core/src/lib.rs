@@ -0,0 +1,17 @@
+//! Parts of the Lily58 firmware that don't depend on the RP2040 or on RMK, so they build and are
+//! tested on the host:
+//! ```sh
+//! cargo test --manifest-path lily58-core/Cargo.toml --target $(rustc -vV | sed -n 's/host: //p')
+//! ```
+//! Times are plain milliseconds, the firmware passes in the current time wherever it's needed.
+#![cfg_attr(not(test), no_std)]
+
+pub mod config;
+pub mod debounce;
+pub mod hid;
+pub mod layout;
+pub mod leader;
+pub mod link;
+pub mod mouse;
+pub mod tap_dance;
+pub mod text;
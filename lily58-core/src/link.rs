@@ -0,0 +1,139 @@
+//! Commands the central sends the peripheral over the split link.
+//!
+//! RMK forwards layer changes from the central to the peripheral, but has no message for anything
+//! else. The commands are encoded as layer numbers from `LINK_COMMAND_BASE` on, past the end of any
+//! keymap, and sent as layer changes.
+
+use crate::debounce::{DebounceAlgorithm, MAX_DEBOUNCE_MS};
+
+/// Every encoded command is at least this, so layers below it are real layers.
+pub const LINK_COMMAND_BASE: u8 = 0x80;
+const SLEEP_COMMAND: u8 = LINK_COMMAND_BASE;
+const WAKE_COMMAND: u8 = LINK_COMMAND_BASE + 1;
+const BOOTLOADER_COMMAND: u8 = LINK_COMMAND_BASE + 2;
+const LOCK_KEY_COMMAND: u8 = LINK_COMMAND_BASE + 3;
+const RESTART_BREATHING_COMMAND: u8 = LINK_COMMAND_BASE + 4;
+const DEBOUNCE_ALGORITHM_BASE: u8 = 0x90;
+const DEBOUNCE_ALGORITHM_END: u8 = 0x9F;
+const LIGHTING_EFFECT_BASE: u8 = 0xA0;
+const LIGHTING_EFFECT_END: u8 = 0xAF;
+const LED_INDICATOR_BASE: u8 = 0xB0;
+const LED_INDICATOR_END: u8 = 0xBF;
+const DEBOUNCE_TIME_BASE: u8 = 0xC0;
+const DEBOUNCE_TIME_END: u8 = DEBOUNCE_TIME_BASE + MAX_DEBOUNCE_MS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkCommand {
+    Sleep,
+    Wake,
+    /// Reboot the peripheral into the USB bootloader
+    Bootloader,
+    /// Hold down the key last pressed on the peripheral
+    LockKey,
+    /// Start the breathing effect's period over, to keep both halves in step
+    RestartBreathing,
+    SetDebounceAlgorithm(DebounceAlgorithm),
+    /// Debounce time in milliseconds, up to `MAX_DEBOUNCE_MS`
+    SetDebounceTime(u8),
+    /// Underglow effect, up to 15
+    SetLightingEffect(u8),
+    /// Lock LED bits, only the lower four are sent
+    SetLedIndicator(u8),
+}
+
+impl LinkCommand {
+    pub const fn encode(self) -> u8 {
+        match self {
+            LinkCommand::Sleep => SLEEP_COMMAND,
+            LinkCommand::Wake => WAKE_COMMAND,
+            LinkCommand::Bootloader => BOOTLOADER_COMMAND,
+            LinkCommand::LockKey => LOCK_KEY_COMMAND,
+            LinkCommand::RestartBreathing => RESTART_BREATHING_COMMAND,
+            LinkCommand::SetDebounceAlgorithm(algorithm) => {
+                DEBOUNCE_ALGORITHM_BASE + algorithm as u8
+            }
+            LinkCommand::SetDebounceTime(time_ms) => {
+                let time_ms = if time_ms < MAX_DEBOUNCE_MS {
+                    time_ms
+                } else {
+                    MAX_DEBOUNCE_MS
+                };
+                DEBOUNCE_TIME_BASE + time_ms
+            }
+            LinkCommand::SetLightingEffect(effect) => LIGHTING_EFFECT_BASE + (effect & 0x0F),
+            LinkCommand::SetLedIndicator(bits) => LED_INDICATOR_BASE + (bits & 0x0F),
+        }
+    }
+
+    /// The command a layer number stands for, `None` for real layers and unknown values.
+    pub const fn decode(layer: u8) -> Option<Self> {
+        match layer {
+            SLEEP_COMMAND => Some(LinkCommand::Sleep),
+            WAKE_COMMAND => Some(LinkCommand::Wake),
+            BOOTLOADER_COMMAND => Some(LinkCommand::Bootloader),
+            LOCK_KEY_COMMAND => Some(LinkCommand::LockKey),
+            RESTART_BREATHING_COMMAND => Some(LinkCommand::RestartBreathing),
+            DEBOUNCE_ALGORITHM_BASE..=DEBOUNCE_ALGORITHM_END => {
+                match DebounceAlgorithm::from_u8(layer - DEBOUNCE_ALGORITHM_BASE) {
+                    Some(algorithm) => Some(LinkCommand::SetDebounceAlgorithm(algorithm)),
+                    None => None,
+                }
+            }
+            LIGHTING_EFFECT_BASE..=LIGHTING_EFFECT_END => {
+                Some(LinkCommand::SetLightingEffect(layer - LIGHTING_EFFECT_BASE))
+            }
+            LED_INDICATOR_BASE..=LED_INDICATOR_END => {
+                Some(LinkCommand::SetLedIndicator(layer - LED_INDICATOR_BASE))
+            }
+            DEBOUNCE_TIME_BASE..=DEBOUNCE_TIME_END => {
+                Some(LinkCommand::SetDebounceTime(layer - DEBOUNCE_TIME_BASE))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut commands = vec![
+            LinkCommand::Sleep,
+            LinkCommand::Wake,
+            LinkCommand::Bootloader,
+            LinkCommand::LockKey,
+            LinkCommand::RestartBreathing,
+        ];
+        let mut algorithm = DebounceAlgorithm::DeferPerKey;
+        for _ in 0..4 {
+            commands.push(LinkCommand::SetDebounceAlgorithm(algorithm));
+            algorithm = algorithm.next();
+        }
+        commands.extend((0..=MAX_DEBOUNCE_MS).map(LinkCommand::SetDebounceTime));
+        commands.extend((0..16).map(LinkCommand::SetLightingEffect));
+        commands.extend((0..16).map(LinkCommand::SetLedIndicator));
+        for command in commands {
+            assert!(command.encode() >= LINK_COMMAND_BASE, "{command:?}");
+            assert_eq!(LinkCommand::decode(command.encode()), Some(command));
+        }
+    }
+
+    #[test]
+    fn layers_are_not_commands() {
+        for layer in 0..LINK_COMMAND_BASE {
+            assert_eq!(LinkCommand::decode(layer), None);
+        }
+    }
+
+    #[test]
+    fn out_of_range_values() {
+        assert_eq!(
+            LinkCommand::SetDebounceTime(MAX_DEBOUNCE_MS + 1).encode(),
+            LinkCommand::SetDebounceTime(MAX_DEBOUNCE_MS).encode()
+        );
+        assert_eq!(LinkCommand::decode(DEBOUNCE_ALGORITHM_BASE + 4), None);
+        assert_eq!(LinkCommand::decode(RESTART_BREATHING_COMMAND + 1), None);
+    }
+}
@@ -0,0 +1,136 @@
+//! Speed of the mouse keys while they're held.
+
+/// How fast a held key ramps up, from the speed on the first press to the top speed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Curve {
+    Linear,
+    /// Slow for longer, for precise positioning
+    Quadratic,
+    Cubic,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Acceleration {
+    /// Distance per report when the key is first pressed
+    pub min_speed: u8,
+    pub max_speed: u8,
+    /// Hold time in milliseconds after which the top speed is reached
+    pub time_to_max_ms: u32,
+    pub curve: Curve,
+}
+
+impl Acceleration {
+    /// Distance per report after the key was held for `held_ms` milliseconds.
+    pub const fn speed(&self, held_ms: u32) -> i8 {
+        let time_to_max = self.time_to_max_ms as u64;
+        // Progress towards the top speed, as a fraction of 256
+        let t = if time_to_max == 0 || held_ms as u64 >= time_to_max {
+            256
+        } else {
+            held_ms as u64 * 256 / time_to_max
+        };
+        let t = match self.curve {
+            Curve::Linear => t,
+            Curve::Quadratic => t * t / 256,
+            Curve::Cubic => t * t * t / (256 * 256),
+        };
+        let range = self.max_speed.saturating_sub(self.min_speed) as u64;
+        let speed = self.min_speed as u64 + range * t / 256;
+        if speed > i8::MAX as u64 {
+            i8::MAX
+        } else {
+            speed as i8
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [Curve; 3] = [Curve::Linear, Curve::Quadratic, Curve::Cubic];
+
+    const fn acceleration(curve: Curve) -> Acceleration {
+        Acceleration {
+            min_speed: 2,
+            max_speed: 20,
+            time_to_max_ms: 1500,
+            curve,
+        }
+    }
+
+    #[test]
+    fn starts_at_min_speed() {
+        for curve in CURVES {
+            assert_eq!(acceleration(curve).speed(0), 2, "{curve:?}");
+        }
+    }
+
+    #[test]
+    fn reaches_max_speed_at_time_to_max() {
+        for curve in CURVES {
+            let acceleration = acceleration(curve);
+            assert!(acceleration.speed(1499) < 20, "{curve:?}");
+            assert_eq!(acceleration.speed(1500), 20, "{curve:?}");
+            assert_eq!(acceleration.speed(60_000), 20, "{curve:?}");
+            assert_eq!(acceleration.speed(u32::MAX), 20, "{curve:?}");
+        }
+    }
+
+    #[test]
+    fn never_slows_down() {
+        for curve in CURVES {
+            let acceleration = acceleration(curve);
+            let mut previous = acceleration.speed(0);
+            for held_ms in 1..2000 {
+                let speed = acceleration.speed(held_ms);
+                assert!(speed >= previous, "{curve:?} slows down at {held_ms}ms");
+                previous = speed;
+            }
+        }
+    }
+
+    #[test]
+    fn steeper_curves_stay_slow_for_longer() {
+        for held_ms in (0..=1500).step_by(100) {
+            let linear = acceleration(Curve::Linear).speed(held_ms);
+            let quadratic = acceleration(Curve::Quadratic).speed(held_ms);
+            let cubic = acceleration(Curve::Cubic).speed(held_ms);
+            assert!(cubic <= quadratic && quadratic <= linear, "at {held_ms}ms");
+        }
+        assert_eq!(acceleration(Curve::Linear).speed(750), 11);
+        assert_eq!(acceleration(Curve::Quadratic).speed(750), 6);
+        assert_eq!(acceleration(Curve::Cubic).speed(750), 4);
+    }
+
+    #[test]
+    fn zero_time_to_max_is_immediately_at_max() {
+        for curve in CURVES {
+            let acceleration = Acceleration {
+                time_to_max_ms: 0,
+                ..acceleration(curve)
+            };
+            assert_eq!(acceleration.speed(0), 20, "{curve:?}");
+            assert_eq!(acceleration.speed(1), 20, "{curve:?}");
+        }
+    }
+
+    #[test]
+    fn clamps_to_report_range() {
+        let fast = Acceleration {
+            min_speed: 100,
+            max_speed: u8::MAX,
+            ..acceleration(Curve::Linear)
+        };
+        assert_eq!(fast.speed(0), 100);
+        assert_eq!(fast.speed(1500), i8::MAX);
+
+        let inverted = Acceleration {
+            min_speed: 20,
+            max_speed: 2,
+            ..acceleration(Curve::Linear)
+        };
+        assert_eq!(inverted.speed(0), 20);
+        assert_eq!(inverted.speed(1500), 20);
+    }
+}
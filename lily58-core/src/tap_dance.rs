@@ -0,0 +1,4 @@
+//! Timing of the tap dances in the keymap, which RMK runs.
+
+/// How long a dance waits for the next tap, or for a key to count as held.
+pub const TAPPING_TERM_MS: u32 = 200;
@@ -0,0 +1,286 @@
+//! Keys to tap to type text, for the keyboard layout the host is set to.
+
+use crate::hid::{MOD_ALTGR, MOD_CTRL, MOD_NONE, MOD_SHIFT};
+
+const USAGE_U: u8 = 0x18;
+const USAGE_SPACE: u8 = 0x2C;
+
+/// Keyboard layout the host is set to, which decides the keys that type each character.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostLayout {
+    Us,
+    Uk,
+    De,
+}
+
+/// How characters the layout has no key for are typed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnicodeMode {
+    /// Skip them
+    None,
+    /// Ctrl+Shift+U, the code point in hex, then Space, as understood by GTK, Qt and IBus
+    Linux,
+}
+
+// Modifiers and HID usage of the key typing `c` on a US layout
+const fn us_tap(c: u8) -> Option<(u8, u8)> {
+    Some(match c {
+        b'a'..=b'z' => (MOD_NONE, 0x04 + c - b'a'),
+        b'A'..=b'Z' => (MOD_SHIFT, 0x04 + c - b'A'),
+        b'1'..=b'9' => (MOD_NONE, 0x1E + c - b'1'),
+        b'0' => (MOD_NONE, 0x27),
+        b'\n' => (MOD_NONE, 0x28),
+        b'\t' => (MOD_NONE, 0x2B),
+        b' ' => (MOD_NONE, USAGE_SPACE),
+        b'!' => (MOD_SHIFT, 0x1E),
+        b'@' => (MOD_SHIFT, 0x1F),
+        b'#' => (MOD_SHIFT, 0x20),
+        b'$' => (MOD_SHIFT, 0x21),
+        b'%' => (MOD_SHIFT, 0x22),
+        b'^' => (MOD_SHIFT, 0x23),
+        b'&' => (MOD_SHIFT, 0x24),
+        b'*' => (MOD_SHIFT, 0x25),
+        b'(' => (MOD_SHIFT, 0x26),
+        b')' => (MOD_SHIFT, 0x27),
+        b'-' => (MOD_NONE, 0x2D),
+        b'_' => (MOD_SHIFT, 0x2D),
+        b'=' => (MOD_NONE, 0x2E),
+        b'+' => (MOD_SHIFT, 0x2E),
+        b'[' => (MOD_NONE, 0x2F),
+        b'{' => (MOD_SHIFT, 0x2F),
+        b']' => (MOD_NONE, 0x30),
+        b'}' => (MOD_SHIFT, 0x30),
+        b'\\' => (MOD_NONE, 0x31),
+        b'|' => (MOD_SHIFT, 0x31),
+        b';' => (MOD_NONE, 0x33),
+        b':' => (MOD_SHIFT, 0x33),
+        b'\'' => (MOD_NONE, 0x34),
+        b'"' => (MOD_SHIFT, 0x34),
+        b'`' => (MOD_NONE, 0x35),
+        b'~' => (MOD_SHIFT, 0x35),
+        b',' => (MOD_NONE, 0x36),
+        b'<' => (MOD_SHIFT, 0x36),
+        b'.' => (MOD_NONE, 0x37),
+        b'>' => (MOD_SHIFT, 0x37),
+        b'/' => (MOD_NONE, 0x38),
+        b'?' => (MOD_SHIFT, 0x38),
+        _ => return None,
+    })
+}
+
+// UK ISO swaps `"` and `@`, and has the extra key next to Enter and left Shift
+const fn uk_tap(c: u8) -> Option<(u8, u8)> {
+    Some(match c {
+        b'"' => (MOD_SHIFT, 0x1F),
+        b'@' => (MOD_SHIFT, 0x34),
+        b'#' => (MOD_NONE, 0x32),
+        b'~' => (MOD_SHIFT, 0x32),
+        b'\\' => (MOD_NONE, 0x64),
+        b'|' => (MOD_SHIFT, 0x64),
+        _ => return us_tap(c),
+    })
+}
+
+// German QWERTZ moves most symbols, some behind AltGr. `^` and `` ` `` are dead keys and left to
+// the unicode mode.
+const fn de_tap(c: u8) -> Option<(u8, u8)> {
+    Some(match c {
+        b'y' => (MOD_NONE, 0x1D),
+        b'Y' => (MOD_SHIFT, 0x1D),
+        b'z' => (MOD_NONE, 0x1C),
+        b'Z' => (MOD_SHIFT, 0x1C),
+        b'"' => (MOD_SHIFT, 0x1F),
+        b'&' => (MOD_SHIFT, 0x23),
+        b'/' => (MOD_SHIFT, 0x24),
+        b'(' => (MOD_SHIFT, 0x25),
+        b')' => (MOD_SHIFT, 0x26),
+        b'=' => (MOD_SHIFT, 0x27),
+        b'?' => (MOD_SHIFT, 0x2D),
+        b'\\' => (MOD_ALTGR, 0x2D),
+        b'+' => (MOD_NONE, 0x30),
+        b'*' => (MOD_SHIFT, 0x30),
+        b'~' => (MOD_ALTGR, 0x30),
+        b'#' => (MOD_NONE, 0x32),
+        b'\'' => (MOD_SHIFT, 0x32),
+        b'<' => (MOD_NONE, 0x64),
+        b'>' => (MOD_SHIFT, 0x64),
+        b'|' => (MOD_ALTGR, 0x64),
+        b';' => (MOD_SHIFT, 0x36),
+        b':' => (MOD_SHIFT, 0x37),
+        b'-' => (MOD_NONE, 0x38),
+        b'_' => (MOD_SHIFT, 0x38),
+        b'@' => (MOD_ALTGR, 0x14),
+        b'{' => (MOD_ALTGR, 0x24),
+        b'[' => (MOD_ALTGR, 0x25),
+        b']' => (MOD_ALTGR, 0x26),
+        b'}' => (MOD_ALTGR, 0x27),
+        b'^' | b'`' => return None,
+        _ => return us_tap(c),
+    })
+}
+
+/// Modifiers and HID usage of the key typing the ASCII character `c` on `layout`.
+pub const fn ascii_tap(layout: HostLayout, c: u8) -> Option<(u8, u8)> {
+    match layout {
+        HostLayout::Us => us_tap(c),
+        HostLayout::Uk => uk_tap(c),
+        HostLayout::De => de_tap(c),
+    }
+}
+
+/// The taps typing `c` through `mode`, for characters `layout` has no key for.
+pub fn unicode_taps(
+    mode: UnicodeMode,
+    layout: HostLayout,
+    c: char,
+) -> impl Iterator<Item = (u8, u8)> {
+    let code = c as u32;
+    let (start, digits, end) = match mode {
+        UnicodeMode::None => (None, 0, None),
+        UnicodeMode::Linux => (
+            Some((MOD_CTRL | MOD_SHIFT, USAGE_U)),
+            (32 - code.leading_zeros()).div_ceil(4).max(1),
+            Some((MOD_NONE, USAGE_SPACE)),
+        ),
+    };
+    let hex = (0..digits).rev().filter_map(move |i| {
+        let digit = char::from_digit((code >> (i * 4)) & 0xF, 16)?;
+        ascii_tap(layout, digit as u8)
+    });
+    start.into_iter().chain(hex).chain(end)
+}
+
+/// The taps typing `text` on `layout`, falling back to `mode` for characters it has no key for.
+pub fn text_taps(
+    layout: HostLayout,
+    mode: UnicodeMode,
+    text: &str,
+) -> impl Iterator<Item = (u8, u8)> + '_ {
+    text.chars().flat_map(move |c| {
+        let ascii = c.is_ascii().then(|| ascii_tap(layout, c as u8)).flatten();
+        let unicode = match ascii {
+            Some(_) => None,
+            None => Some(unicode_taps(mode, layout, c)),
+        };
+        ascii.into_iter().chain(unicode.into_iter().flatten())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UK: HostLayout = HostLayout::Uk;
+    const US: HostLayout = HostLayout::Us;
+    const DE: HostLayout = HostLayout::De;
+
+    fn taps(layout: HostLayout, mode: UnicodeMode, text: &str) -> Vec<(u8, u8)> {
+        text_taps(layout, mode, text).collect()
+    }
+
+    #[test]
+    fn quote_and_at_swap_on_uk() {
+        assert_eq!(ascii_tap(US, b'"'), Some((MOD_SHIFT, 0x34)));
+        assert_eq!(ascii_tap(US, b'@'), Some((MOD_SHIFT, 0x1F)));
+        assert_eq!(ascii_tap(UK, b'"'), Some((MOD_SHIFT, 0x1F)));
+        assert_eq!(ascii_tap(UK, b'@'), Some((MOD_SHIFT, 0x34)));
+        assert_eq!(ascii_tap(UK, b'#'), Some((MOD_NONE, 0x32)));
+        assert_eq!(ascii_tap(UK, b'\\'), Some((MOD_NONE, 0x64)));
+    }
+
+    #[test]
+    fn y_and_z_swap_on_de() {
+        assert_eq!(ascii_tap(US, b'y'), Some((MOD_NONE, 0x1C)));
+        assert_eq!(ascii_tap(US, b'z'), Some((MOD_NONE, 0x1D)));
+        assert_eq!(ascii_tap(DE, b'y'), Some((MOD_NONE, 0x1D)));
+        assert_eq!(ascii_tap(DE, b'z'), Some((MOD_NONE, 0x1C)));
+        assert_eq!(ascii_tap(DE, b'Y'), Some((MOD_SHIFT, 0x1D)));
+        assert_eq!(ascii_tap(DE, b'Z'), Some((MOD_SHIFT, 0x1C)));
+    }
+
+    #[test]
+    fn de_symbols_behind_altgr() {
+        assert_eq!(ascii_tap(DE, b'@'), Some((MOD_ALTGR, 0x14)));
+        assert_eq!(ascii_tap(DE, b'{'), Some((MOD_ALTGR, 0x24)));
+        assert_eq!(ascii_tap(DE, b'['), Some((MOD_ALTGR, 0x25)));
+        assert_eq!(ascii_tap(DE, b']'), Some((MOD_ALTGR, 0x26)));
+        assert_eq!(ascii_tap(DE, b'}'), Some((MOD_ALTGR, 0x27)));
+        assert_eq!(ascii_tap(DE, b'\\'), Some((MOD_ALTGR, 0x2D)));
+        assert_eq!(ascii_tap(DE, b'~'), Some((MOD_ALTGR, 0x30)));
+        assert_eq!(ascii_tap(DE, b'|'), Some((MOD_ALTGR, 0x64)));
+    }
+
+    #[test]
+    fn de_dead_keys_have_no_tap() {
+        assert_eq!(ascii_tap(DE, b'^'), None);
+        assert_eq!(ascii_tap(DE, b'`'), None);
+        assert_eq!(taps(DE, UnicodeMode::None, "^`"), []);
+        assert_eq!(
+            taps(DE, UnicodeMode::Linux, "^"),
+            [
+                (MOD_CTRL | MOD_SHIFT, USAGE_U),
+                (MOD_NONE, 0x22),
+                (MOD_NONE, 0x08),
+                (MOD_NONE, USAGE_SPACE),
+            ]
+        );
+    }
+
+    #[test]
+    fn every_printable_character_has_a_tap_on_us_and_uk() {
+        for c in b' '..=b'~' {
+            assert!(ascii_tap(US, c).is_some(), "{}", c as char);
+            assert!(ascii_tap(UK, c).is_some(), "{}", c as char);
+        }
+        assert_eq!(ascii_tap(US, 0x7F), None);
+        assert_eq!(ascii_tap(US, b'\r'), None);
+    }
+
+    #[test]
+    fn unicode_hex_digits() {
+        // U+30C4, then U+1F600 with more than 4 digits
+        let expected = [
+            (MOD_CTRL | MOD_SHIFT, USAGE_U),
+            (MOD_NONE, 0x20),
+            (MOD_NONE, 0x27),
+            (MOD_NONE, 0x06),
+            (MOD_NONE, 0x21),
+            (MOD_NONE, USAGE_SPACE),
+        ];
+        assert_eq!(
+            unicode_taps(UnicodeMode::Linux, US, 'ツ').collect::<Vec<_>>(),
+            expected
+        );
+        let digits: Vec<_> = unicode_taps(UnicodeMode::Linux, US, '😀')
+            .map(|(_, usage)| usage)
+            .collect();
+        assert_eq!(digits, [USAGE_U, 0x1E, 0x09, 0x23, 0x27, 0x27, USAGE_SPACE]);
+        assert_eq!(unicode_taps(UnicodeMode::None, US, 'ツ').count(), 0);
+    }
+
+    #[test]
+    fn unicode_hex_digits_follow_the_layout() {
+        // Hex digits are on the same keys on every layout supported
+        let us: Vec<_> = unicode_taps(UnicodeMode::Linux, US, '\u{BD}').collect();
+        let de: Vec<_> = unicode_taps(UnicodeMode::Linux, DE, '\u{BD}').collect();
+        assert_eq!(us, de);
+        assert_eq!(us[1..3], [(MOD_NONE, 0x05), (MOD_NONE, 0x07)]);
+    }
+
+    #[test]
+    fn text_mixes_ascii_and_unicode() {
+        assert_eq!(
+            taps(US, UnicodeMode::Linux, "a_é\n"),
+            [
+                (MOD_NONE, 0x04),
+                (MOD_SHIFT, 0x2D),
+                (MOD_CTRL | MOD_SHIFT, USAGE_U),
+                (MOD_NONE, 0x08),
+                (MOD_NONE, 0x26),
+                (MOD_NONE, USAGE_SPACE),
+                (MOD_NONE, 0x28),
+            ]
+        );
+        assert_eq!(taps(US, UnicodeMode::None, "aé"), [(MOD_NONE, 0x04)]);
+    }
+}
@@ -1,6 +1,6 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 16384K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = FIRMWARE_END - 0x100
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
@@ -0,0 +1,51 @@
+use rmk::{
+    event::ControllerEvent,
+    types::{
+        action::{Action, KeyAction},
+        keycode::KeyCode,
+        modifier::ModifierCombination,
+    },
+};
+
+use crate::events;
+use crate::keymap::{ASH, AUTO_SHIFT_LAYER, AUTO_SHIFT_POSITIONS};
+use crate::live_keymap::{fill_overlay, LiveKeymap};
+
+// Arguments are right, gui, alt, shift, ctrl
+const SHIFT: ModifierCombination = ModifierCombination::new_from(false, false, false, true, false);
+
+// Letters, digits and symbols
+fn is_auto_shift_key(key: KeyCode) -> bool {
+    let code = key as u16;
+    (KeyCode::A as u16..=KeyCode::Kc0 as u16).contains(&code)
+        || (KeyCode::Minus as u16..=KeyCode::Slash as u16).contains(&code)
+}
+
+// Sends the shifted key when held, using RMK's default tap-hold timeout, left alone so that turning
+// auto-shift on doesn't change the timing of tap-hold keys set up from Vial
+fn auto_shifted(row: usize, col: usize, action: KeyAction) -> Option<KeyAction> {
+    match action {
+        KeyAction::Single(Action::Key(key))
+            if AUTO_SHIFT_POSITIONS[row][col] && is_auto_shift_key(key) =>
+        {
+            Some(KeyAction::TapHold(
+                Action::Key(key),
+                Action::KeyWithModifier(key, SHIFT),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Keeps the auto-shift layer turned on by `ASH` in step with the base layer, filling it at startup
+/// and again whenever `ASH` is pressed, so it follows the keys remapped from Vial.
+pub(crate) async fn run_auto_shift(keymap: &LiveKeymap<'_>) -> ! {
+    let mut subscriber = events::subscribe("auto-shift").await;
+    loop {
+        fill_overlay(keymap, AUTO_SHIFT_LAYER, auto_shifted);
+        while !matches!(
+            subscriber.next_message_pure().await,
+            ControllerEvent::Key(event, action) if event.pressed && action == ASH
+        ) {}
+    }
+}
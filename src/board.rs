@@ -0,0 +1,144 @@
+// Pin assignments for the supported controllers, selected with a `board-*` feature. The Lily58 PCB
+// is wired for the Pro Micro pinout, so each profile maps those pins to the controller's GPIOs:
+//   rows: C6, D7, E6, B4, B5
+//   cols: F6, F7, B1, B3, B2, B6
+//   split link: D2, plus D3 for `full-duplex`
+//   OLED: D1 (SDA), D0 (SCL)
+//   underglow: D3
+#[cfg(feature = "display")]
+use embassy_rp::bind_interrupts;
+#[cfg(feature = "display")]
+use embassy_rp::i2c::InterruptHandler;
+
+use crate::keymap::{COLS, ROWS};
+
+// The Lily58 PCB has col2row diodes. Boards wired the other way need this flipped along with the
+// `diode` in `board_matrix_pins!`.
+pub(crate) const COL2ROW: bool = true;
+pub(crate) const INPUT_PIN_NUM: usize = if COL2ROW { ROWS } else { COLS };
+pub(crate) const OUTPUT_PIN_NUM: usize = if COL2ROW { COLS } else { ROWS };
+
+#[cfg(not(any(
+    feature = "board-blok",
+    feature = "board-elite-pi",
+    feature = "board-kb2040",
+    feature = "board-sea-picro"
+)))]
+compile_error!("Enable one of the `board-*` features");
+
+#[cfg(any(
+    all(
+        feature = "board-blok",
+        any(
+            feature = "board-elite-pi",
+            feature = "board-kb2040",
+            feature = "board-sea-picro"
+        )
+    ),
+    all(
+        feature = "board-elite-pi",
+        any(feature = "board-kb2040", feature = "board-sea-picro")
+    ),
+    all(feature = "board-kb2040", feature = "board-sea-picro"),
+))]
+compile_error!("Only one `board-*` feature can be enabled, use `--no-default-features` to replace `board-blok`");
+
+#[cfg(any(
+    feature = "board-blok",
+    feature = "board-elite-pi",
+    feature = "board-sea-picro"
+))]
+macro_rules! board_matrix_pins {
+    ($p:ident) => {
+        config_matrix_pins_rp!(
+            peripherals: $p,
+            rows: [PIN_5, PIN_6, PIN_7, PIN_8, PIN_9],
+            cols: [PIN_27, PIN_26, PIN_22, PIN_20, PIN_23, PIN_21],
+            diode: col2row
+        )
+    };
+}
+
+#[cfg(feature = "board-kb2040")]
+macro_rules! board_matrix_pins {
+    ($p:ident) => {
+        config_matrix_pins_rp!(
+            peripherals: $p,
+            rows: [PIN_5, PIN_6, PIN_7, PIN_8, PIN_9],
+            cols: [PIN_27, PIN_26, PIN_18, PIN_20, PIN_19, PIN_10],
+            diode: col2row
+        )
+    };
+}
+
+// D2 and D3 are on GP1 and GP0 on every supported board. The halves are connected by a straight
+// TRRS cable, so they swap TX and RX.
+macro_rules! board_split_uart {
+    (central, $p:ident, $rx_buf:expr, $irqs:ident) => {
+        config_split_uart_rp!(
+            peripherals: $p,
+            half_duplex: PIN_1,
+            full_duplex: { tx: PIN_1, rx: PIN_0 },
+            rx_buf: $rx_buf,
+            irqs: $irqs
+        )
+    };
+    (peripheral, $p:ident, $rx_buf:expr, $irqs:ident) => {
+        config_split_uart_rp!(
+            peripherals: $p,
+            half_duplex: PIN_1,
+            full_duplex: { tx: PIN_0, rx: PIN_1 },
+            rx_buf: $rx_buf,
+            irqs: $irqs
+        )
+    };
+}
+
+// F5 and F4 are free on the Lily58 PCB, and on GP28 and GP29 on every supported board
+macro_rules! board_encoder_pins {
+    ($p:ident) => {
+        (
+            Input::new($p.PIN_28, embassy_rp::gpio::Pull::Up),
+            Input::new($p.PIN_29, embassy_rp::gpio::Pull::Up),
+        )
+    };
+}
+
+#[cfg(all(feature = "rgb", feature = "full-duplex"))]
+compile_error!("`rgb` and `full-duplex` both use D3, enable only one of them");
+
+// D3 is GP0 on every supported board
+#[allow(unused_macros)]
+macro_rules! board_rgb_pin {
+    ($p:ident) => {
+        $p.PIN_0
+    };
+}
+
+#[cfg(all(feature = "display", feature = "board-blok"))]
+pub(crate) type OledI2c = embassy_rp::peripherals::I2C0;
+#[cfg(all(feature = "display", feature = "board-blok"))]
+bind_interrupts!(pub(crate) struct OledIrqs {
+    I2C0_IRQ => InterruptHandler<OledI2c>;
+});
+#[cfg(feature = "board-blok")]
+#[allow(unused_macros)]
+macro_rules! board_oled_pins {
+    ($p:ident) => {
+        ($p.I2C0, $p.PIN_16, $p.PIN_17)
+    };
+}
+
+#[cfg(all(feature = "display", not(feature = "board-blok")))]
+pub(crate) type OledI2c = embassy_rp::peripherals::I2C1;
+#[cfg(all(feature = "display", not(feature = "board-blok")))]
+bind_interrupts!(pub(crate) struct OledIrqs {
+    I2C1_IRQ => InterruptHandler<OledI2c>;
+});
+#[cfg(not(feature = "board-blok"))]
+#[allow(unused_macros)]
+macro_rules! board_oled_pins {
+    ($p:ident) => {
+        ($p.I2C1, $p.PIN_2, $p.PIN_3)
+    };
+}
@@ -0,0 +1,101 @@
+use embassy_rp::gpio::{Input, Output};
+use embassy_time::Duration;
+
+use crate::board::COL2ROW;
+
+/// A key in this half's matrix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct KeyPosition {
+    pub row: usize,
+    pub col: usize,
+}
+
+impl KeyPosition {
+    pub(crate) const fn from_pins(in_idx: usize, out_idx: usize) -> Self {
+        if COL2ROW {
+            Self {
+                row: in_idx,
+                col: out_idx,
+            }
+        } else {
+            Self {
+                row: out_idx,
+                col: in_idx,
+            }
+        }
+    }
+
+    /// The `(input pin index, output pin index)` the key is read with.
+    pub(crate) const fn pins(self) -> (usize, usize) {
+        if COL2ROW {
+            (self.row, self.col)
+        } else {
+            (self.col, self.row)
+        }
+    }
+}
+
+/// Something to do when a key is held while plugging in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BootAction {
+    /// Reboot into the RP2040 USB bootloader
+    Bootloader,
+    /// Wipe everything saved to flash and start over from the defaults
+    ClearStorage,
+    /// Use the defaults for this boot only, leaving what's saved to flash alone
+    SafeMode,
+    /// Run without the split link, e.g. to test one half on its own
+    DisableSplit,
+}
+
+/// Keys to hold while plugging in, the same on both halves: the peripheral's matrix is mirrored,
+/// so these are the four outermost keys of the number row on either side. That's Escape, 1, 2 and 3
+/// on the left half and =, 0, 9 and 8 on the right one.
+pub(crate) const BOOT_MAGIC: [(KeyPosition, BootAction); 4] = [
+    (KeyPosition { row: 0, col: 0 }, BootAction::Bootloader),
+    (KeyPosition { row: 0, col: 1 }, BootAction::ClearStorage),
+    (KeyPosition { row: 0, col: 2 }, BootAction::SafeMode),
+    (KeyPosition { row: 0, col: 3 }, BootAction::DisableSplit),
+];
+
+/// The boot actions requested by the held keys, other than entering the bootloader.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct BootMagic {
+    pub clear_storage: bool,
+    pub safe_mode: bool,
+    pub disable_split: bool,
+}
+
+/// Checks a single key before the matrix starts scanning, using the pins from
+/// `config_matrix_pins_rp!`.
+pub(crate) fn is_key_pressed(
+    input_pins: &[Input],
+    output_pins: &mut [Output],
+    key: KeyPosition,
+) -> bool {
+    let (in_idx, out_idx) = key.pins();
+    let output = &mut output_pins[out_idx];
+    output.set_high();
+    embassy_time::block_for(Duration::from_millis(1));
+    let pressed = input_pins[in_idx].is_high();
+    output.set_low();
+    pressed
+}
+
+/// Runs the actions for every key in `BOOT_MAGIC` that is held while plugging in. Doesn't return
+/// if one of them enters the bootloader.
+pub(crate) fn check_boot_magic(input_pins: &[Input], output_pins: &mut [Output]) -> BootMagic {
+    let mut magic = BootMagic::default();
+    for (key, action) in BOOT_MAGIC {
+        if !is_key_pressed(input_pins, output_pins, key) {
+            continue;
+        }
+        match action {
+            BootAction::Bootloader => embassy_rp::rom_data::reset_to_usb_boot(0, 0),
+            BootAction::ClearStorage => magic.clear_storage = true,
+            BootAction::SafeMode => magic.safe_mode = true,
+            BootAction::DisableSplit => magic.disable_split = true,
+        }
+    }
+    magic
+}
@@ -0,0 +1,96 @@
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use rmk::{
+    event::ControllerEvent,
+    types::{
+        action::{Action, KeyAction},
+        keycode::KeyCode,
+        modifier::ModifierCombination,
+    },
+};
+
+use crate::events;
+use crate::keymap::{CAPS_WORD_LAYER, CWD};
+use crate::live_keymap::{fill_overlay, set_layer, LiveKeymap};
+
+// Caps Word ends by itself after this long without a key press
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+// Arguments are right, gui, alt, shift, ctrl
+const LEFT_SHIFT: ModifierCombination =
+    ModifierCombination::new_from(false, false, false, true, false);
+
+fn is_letter(key: KeyCode) -> bool {
+    (KeyCode::A as u16..=KeyCode::Z as u16).contains(&(key as u16))
+}
+
+// Letters, digits, `-`, Backspace, Delete and modifiers, `_` is a shifted `-`
+fn continues_word(key: KeyCode) -> bool {
+    let code = key as u16;
+    (KeyCode::A as u16..=KeyCode::Kc0 as u16).contains(&code)
+        || (KeyCode::LCtrl as u16..=KeyCode::RGui as u16).contains(&code)
+        || matches!(key, KeyCode::Minus | KeyCode::Backspace | KeyCode::Delete)
+}
+
+fn ends_word(action: KeyAction) -> bool {
+    // A tap-hold key counts as its tap action
+    let (KeyAction::Single(action) | KeyAction::TapHold(action, _)) = action else {
+        return false;
+    };
+    match action {
+        Action::Key(key) => !continues_word(key),
+        // Including the letters shifted by the Caps Word layer
+        Action::KeyWithModifier(key, _) => !is_letter(key) && key != KeyCode::Minus,
+        // Layer keys and other behaviors keep the word going
+        _ => false,
+    }
+}
+
+// Shifts the letters of the base layer only while they're pressed, like QMK's weak left shift, so
+// the host's caps lock and shift keys are left alone.
+fn shifted_letter(action: KeyAction) -> Option<KeyAction> {
+    match action {
+        KeyAction::Single(Action::Key(key)) if is_letter(key) => {
+            Some(KeyAction::Single(Action::KeyWithModifier(key, LEFT_SHIFT)))
+        }
+        KeyAction::TapHold(Action::Key(key), hold) if is_letter(key) => Some(KeyAction::TapHold(
+            Action::KeyWithModifier(key, LEFT_SHIFT),
+            hold,
+        )),
+        _ => None,
+    }
+}
+
+/// Shifts the letters typed after `CWD` is pressed, through the Caps Word layer, until the first
+/// key that can't be part of a word, or when idle. Pressing `CWD` again ends it.
+pub(crate) async fn run_caps_word(keymap: &LiveKeymap<'_>) -> ! {
+    let mut subscriber = events::subscribe("Caps Word").await;
+    let mut active = false;
+    loop {
+        let deadline = if active {
+            Instant::now() + IDLE_TIMEOUT
+        } else {
+            Instant::MAX
+        };
+        let activate = match select(subscriber.next_message_pure(), Timer::at(deadline)).await {
+            Either::First(ControllerEvent::Key(event, action)) if event.pressed => {
+                if action == CWD {
+                    !active
+                } else if active && ends_word(action) {
+                    false
+                } else {
+                    continue;
+                }
+            }
+            Either::First(_) => continue,
+            Either::Second(()) => false,
+        };
+        if activate {
+            // Follows the letters wherever they are on the base layer now
+            fill_overlay(keymap, CAPS_WORD_LAYER, |_, _, action| {
+                shifted_letter(action)
+            });
+        }
+        set_layer(keymap, CAPS_WORD_LAYER, activate);
+        active = activate;
+    }
+}
@@ -5,40 +5,102 @@
 mod keymap;
 #[macro_use]
 mod macros;
+mod auto_shift;
+#[macro_use]
+mod board;
+mod boot;
+mod caps_word;
+mod central_sync;
+mod chatter;
+mod debounce;
+mod dynamic_macros;
+mod events;
+mod flash_config;
+mod key_lock;
 mod keyboard_macros;
+mod layout_version;
+mod leader;
+#[cfg(feature = "rgb")]
+mod lighting;
+mod live_keymap;
+mod mouse_keys;
+#[cfg(feature = "display")]
+mod oled;
+mod one_shot;
+mod settings_keys;
+mod split_sync;
+mod storage;
+
 use embassy_executor::Spawner;
 use embassy_rp::flash::Flash;
 use embassy_rp::gpio::{Input, Output};
 use embassy_rp::peripherals::{PIO0, USB};
 use embassy_rp::usb::{Driver, InterruptHandler};
 use embassy_rp::{bind_interrupts, flash};
+use embassy_sync::mutex::Mutex;
+use embassy_time::Duration;
 use panic_probe as _;
 use rmk::channel::EVENT_CHANNEL;
-use rmk::config::{BehaviorConfig, DeviceConfig, PositionalConfig, RmkConfig, StorageConfig, VialConfig};
-use rmk::debounce::default_debouncer::DefaultDebouncer;
-use rmk::futures::future::join4;
+use rmk::config::{
+    BehaviorConfig, DeviceConfig, OneShotConfig, PositionalConfig, RmkConfig, StorageConfig,
+    VialConfig,
+};
+use rmk::futures::future::{join3, join5};
+use rmk::input_device::rotary_encoder::RotaryEncoder;
 use rmk::input_device::Runnable;
 use rmk::keyboard::Keyboard;
 use rmk::split::central::{run_peripheral_manager, CentralMatrix};
 use rmk::split::rp::uart::{BufferedUart, UartInterruptHandler};
 use rmk::split::SPLIT_MESSAGE_MAX_SIZE;
-use rmk::{initialize_keymap_and_storage, run_devices, run_rmk};
+use rmk::{initialize_encoder_keymap, initialize_encoder_keymap_and_storage, run_devices, run_rmk};
+#[cfg(feature = "display")]
+use ssd1306::{mode::TerminalModeAsync, prelude::DisplayRotation};
 use static_cell::StaticCell;
 
-use crate::keyboard_macros::get_forks;
+use crate::auto_shift::run_auto_shift;
+use crate::board::{COL2ROW, INPUT_PIN_NUM, OUTPUT_PIN_NUM};
+use crate::boot::check_boot_magic;
+use crate::caps_word::run_caps_word;
+#[cfg(feature = "rgb")]
+use crate::central_sync::run_breathing_sync;
+use crate::central_sync::run_central_sync;
+use crate::chatter::ChatterDetector;
+use crate::debounce::{set_debounce_config, Debouncer};
+use crate::dynamic_macros::run_dynamic_macros;
+use crate::events::run_event_forwarder;
+use crate::flash_config::{DEFAULT_CONFIG, FLASH_SIZE};
+use crate::key_lock::KeyLock;
+use crate::keyboard_macros::{get_forks, run_text_macros};
 use crate::keymap::{COLS, ROWS};
+use crate::layout_version::{check_layout, record_layout};
+use crate::leader::run_leader;
+#[cfg(feature = "rgb")]
+use crate::lighting::{init_lighting, run_lighting, KeyActivity, Leds};
+use crate::mouse_keys::run_mouse_keys;
+#[cfg(feature = "display")]
+use crate::oled::{init_oled_terminal, run_status_display, Oled};
+use crate::one_shot::run_one_shot_lock;
+use crate::settings_keys::run_settings_keys;
+use crate::split_sync::run_state_sync;
+use crate::storage::{RmkFlash, SharedFlash, RMK_STORAGE_OFFSET, RMK_STORAGE_SECTORS};
 
 bind_interrupts!(struct Irqs {
     USBCTRL_IRQ => InterruptHandler<USB>;
     PIO0_IRQ_0 => UartInterruptHandler<PIO0>;
 });
 
-const FLASH_SIZE: usize = 2 * 1024 * 1024;
 const ROW_OFFSET: usize = ROWS;
 const COL_OFFSET: usize = 0;
+const SLEEP_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+// One-shot keys are forgotten if nothing else is pressed within this time
+const ONE_SHOT_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
+#[cfg_attr(
+    not(any(feature = "display", feature = "rgb")),
+    allow(unused_variables)
+)]
+async fn main(spawner: Spawner) {
     // Initialize peripherals
     let p = embassy_rp::init(Default::default());
 
@@ -46,14 +108,32 @@ async fn main(_spawner: Spawner) {
     let usb_driver = Driver::new(p.USB, Irqs);
 
     // Pin config
-    let (row_pins, col_pins) = config_matrix_pins_rp!(
-        peripherals: p,
-        input: [PIN_5, PIN_6, PIN_7, PIN_8, PIN_9],
-        output: [PIN_27, PIN_26, PIN_22, PIN_20, PIN_23, PIN_21]
-    );
+    let (input_pins, mut output_pins) = board_matrix_pins!(p);
+    let boot_magic = check_boot_magic(&input_pins, &mut output_pins);
 
-    // Use internal flash to emulate eeprom
-    let flash = Flash::<_, flash::Async, FLASH_SIZE>::new(p.FLASH, p.DMA_CH0);
+    // Use internal flash to emulate eeprom. Board settings RMK doesn't know about live next to its
+    // storage, so the flash is shared with it.
+    let flash: SharedFlash = Mutex::new(Flash::<_, flash::Async, FLASH_SIZE>::new(
+        p.FLASH, p.DMA_CH0,
+    ));
+    let (board_config, layout_check) = {
+        let mut config_flash = flash.lock().await;
+        if boot_magic.clear_storage {
+            flash_config::store(&mut *config_flash, DEFAULT_CONFIG);
+            dynamic_macros::clear(&mut *config_flash);
+        }
+        let board_config = if boot_magic.safe_mode {
+            DEFAULT_CONFIG
+        } else {
+            flash_config::load(&mut *config_flash)
+        };
+        // A keymap saved for a different layout would be misinterpreted, so start over instead
+        (
+            board_config,
+            (!boot_magic.safe_mode).then(|| check_layout(&mut *config_flash)),
+        )
+    };
+    set_debounce_config(board_config.debounce);
 
     let keyboard_device_config = DeviceConfig {
         vid: 0x4c4b,
@@ -70,43 +150,134 @@ async fn main(_spawner: Spawner) {
 
     static RX_BUF: StaticCell<[u8; SPLIT_MESSAGE_MAX_SIZE]> = StaticCell::new();
     let rx_buf = &mut RX_BUF.init([0; SPLIT_MESSAGE_MAX_SIZE])[..];
-    let uart_receiver = BufferedUart::new_half_duplex(p.PIO0, p.PIN_1, rx_buf, Irqs);
+    let uart_receiver = board_split_uart!(central, p, rx_buf, Irqs);
 
     // Initialize the storage and keymap
     let mut default_keymap = keymap::get_default_keymap();
+    let mut default_encoder_map = keymap::get_default_encoder_map();
 
-    let mut behavior_config = BehaviorConfig {
-        fork: get_forks(),
-        ..BehaviorConfig::default()
+    let mut behavior_config = default_behavior_config();
+    let storage_config = StorageConfig {
+        start_addr: RMK_STORAGE_OFFSET,
+        num_sectors: RMK_STORAGE_SECTORS,
+        clear_storage: boot_magic.clear_storage
+            || layout_check.as_ref().is_some_and(|check| check.changed()),
+        ..StorageConfig::default()
     };
-    let storage_config = StorageConfig::default();
     let mut per_key_config = PositionalConfig::default();
-    let (keymap, mut storage) = initialize_keymap_and_storage(
+    let (stored_keymap, mut storage) = initialize_encoder_keymap_and_storage(
         &mut default_keymap,
-        flash,
+        &mut default_encoder_map,
+        RmkFlash(&flash),
         &storage_config,
         &mut behavior_config,
         &mut per_key_config,
     )
     .await;
+    // Only now that the storage is cleared, so losing power before this clears it again next boot
+    if let Some(check) = layout_check {
+        record_layout(&mut *flash.lock().await, check);
+    }
+
+    // Safe mode ignores the saved keymap, storage is still needed for Vial to work
+    let mut safe_keymap = keymap::get_default_keymap();
+    let mut safe_encoder_map = keymap::get_default_encoder_map();
+    let mut safe_behavior_config = default_behavior_config();
+    let mut safe_per_key_config = PositionalConfig::default();
+    let keymap = if boot_magic.safe_mode {
+        initialize_encoder_keymap(
+            &mut safe_keymap,
+            &mut safe_encoder_map,
+            &mut safe_behavior_config,
+            &mut safe_per_key_config,
+        )
+        .await
+    } else {
+        stored_keymap
+    };
 
     // Initialize the matrix + keyboard
-    let debouncer = DefaultDebouncer::<ROWS, COLS>::new();
-    let mut matrix =
-        CentralMatrix::<_, _, _, 0, 0, ROWS, COLS, true>::new(row_pins, col_pins, debouncer);
+    let debouncer = ChatterDetector::new(Debouncer::<INPUT_PIN_NUM, OUTPUT_PIN_NUM>::new());
+    #[cfg(feature = "rgb")]
+    let debouncer = KeyActivity::new(debouncer);
+    let debouncer = KeyLock::new(debouncer);
+    let mut matrix = CentralMatrix::<_, _, _, 0, 0, INPUT_PIN_NUM, OUTPUT_PIN_NUM, COL2ROW>::new(
+        input_pins,
+        output_pins,
+        debouncer,
+    );
+
+    let (encoder_pin_a, encoder_pin_b) = board_encoder_pins!(p);
+    let mut encoder = RotaryEncoder::with_resolution(encoder_pin_a, encoder_pin_b, 4, false, 0);
 
     let mut keyboard = Keyboard::new(&keymap);
 
-    // initialize the OLED display
-    // let display = init_oled_terminal(p.I2C0, p.PIN_16, p.PIN_17, DisplayRotation::Rotate90).await;
-    // spawner.spawn(key_display_task(display)).unwrap();
-    
+    // Initialize the OLED display
+    #[cfg(feature = "display")]
+    {
+        let (i2c, sda, scl) = board_oled_pins!(p);
+        let display = init_oled_terminal(i2c, sda, scl, DisplayRotation::Rotate0).await;
+        spawner.spawn(display_task(display)).unwrap();
+    }
+
+    // Initialize the underglow
+    #[cfg(feature = "rgb")]
+    {
+        let leds = init_lighting(p.PIO1, p.DMA_CH1, board_rgb_pin!(p));
+        spawner.spawn(lighting_task(leds)).unwrap();
+    }
+
     // Start
-    join4(
-        run_devices! ((matrix) => EVENT_CHANNEL),
+    join5(
+        run_devices! ((matrix, encoder) => EVENT_CHANNEL),
         keyboard.run(),
-        run_peripheral_manager::<ROWS, COLS, ROW_OFFSET, COL_OFFSET, _>(0, uart_receiver),
+        async {
+            if !boot_magic.disable_split {
+                run_peripheral_manager::<ROWS, COLS, ROW_OFFSET, COL_OFFSET, _>(0, uart_receiver)
+                    .await
+            }
+        },
         run_rmk(usb_driver, &mut storage, rmk_config),
+        join5(
+            run_event_forwarder(),
+            run_state_sync(),
+            run_central_sync(SLEEP_TIMEOUT),
+            join3(
+                run_settings_keys(&flash),
+                run_one_shot_lock(),
+                run_dynamic_macros(&flash),
+            ),
+            join5(
+                run_auto_shift(&keymap),
+                run_caps_word(&keymap),
+                run_leader(&keymap),
+                run_text_macros(),
+                run_mouse_keys(),
+            ),
+        ),
     )
     .await;
 }
+
+fn default_behavior_config() -> BehaviorConfig {
+    BehaviorConfig {
+        fork: get_forks(),
+        tap_dance: keymap::get_tap_dances(),
+        one_shot: OneShotConfig {
+            timeout: ONE_SHOT_TIMEOUT,
+        },
+        ..BehaviorConfig::default()
+    }
+}
+
+#[cfg(feature = "display")]
+#[embassy_executor::task]
+async fn display_task(display: Oled<TerminalModeAsync>) {
+    run_status_display(display).await
+}
+
+#[cfg(feature = "rgb")]
+#[embassy_executor::task]
+async fn lighting_task(leds: Leds) {
+    rmk::futures::future::join(run_lighting(leds), run_breathing_sync()).await;
+}
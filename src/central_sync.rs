@@ -0,0 +1,96 @@
+use embassy_futures::select::{select3, Either3};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant, Timer};
+use lily58_core::link::LinkCommand;
+use rmk::{
+    channel::CONTROLLER_CHANNEL, event::ControllerEvent, types::led_indicator::LedIndicator,
+};
+
+use crate::events;
+use crate::keymap::PBL;
+#[cfg(feature = "rgb")]
+use crate::lighting::{lighting_effect, restart_breathing, LightingEffect, BREATHING_PERIOD_MS};
+
+// Commands waiting for `run_central_sync` to send them
+static LINK_COMMANDS: Channel<CriticalSectionRawMutex, LinkCommand, 8> = Channel::new();
+
+/// Queues a command for the peripheral, waiting for room if the queue is full.
+pub(crate) async fn send_link_command(command: LinkCommand) {
+    LINK_COMMANDS.send(command).await;
+}
+
+/// Sends the queued link commands to the peripheral, along with the ones it raises itself: the
+/// lock LEDs set by the host whenever they change or the peripheral connects, as RMK only reports
+/// them on the central; sleep after `sleep_timeout` without key presses and wake on the next one;
+/// and the USB bootloader when `PBL` is pressed, so the peripheral can be flashed without
+/// unplugging it.
+pub(crate) async fn run_central_sync(sleep_timeout: Duration) -> ! {
+    let mut subscriber = events::subscribe("central sync").await;
+    // The only publisher of link commands. It waits for room in the channel rather than dropping
+    // the oldest message when it's full, which could be a command the peripheral never gets.
+    let publisher = match CONTROLLER_CHANNEL.publisher() {
+        Ok(publisher) => Some(publisher),
+        Err(_) => {
+            log::error!("No controller channel publisher left, link commands may be dropped");
+            None
+        }
+    };
+    let mut led_indicator = LedIndicator::default();
+    let mut deadline = Instant::now() + sleep_timeout;
+    let mut sleeping = false;
+    loop {
+        let command = match select3(
+            subscriber.next_message_pure(),
+            LINK_COMMANDS.receive(),
+            Timer::at(deadline),
+        )
+        .await
+        {
+            Either3::First(ControllerEvent::Key(event, action)) => {
+                deadline = Instant::now() + sleep_timeout;
+                if event.pressed && action == PBL {
+                    LinkCommand::Bootloader
+                } else if sleeping {
+                    sleeping = false;
+                    LinkCommand::Wake
+                } else {
+                    continue;
+                }
+            }
+            Either3::First(ControllerEvent::KeyboardIndicator(new_indicator)) => {
+                led_indicator = new_indicator;
+                LinkCommand::SetLedIndicator(led_indicator.into_bits())
+            }
+            Either3::First(ControllerEvent::SplitPeripheral(_, true)) => {
+                LinkCommand::SetLedIndicator(led_indicator.into_bits())
+            }
+            Either3::First(_) => continue,
+            Either3::Second(command) => command,
+            Either3::Third(()) => {
+                sleeping = true;
+                deadline = Instant::MAX;
+                LinkCommand::Sleep
+            }
+        };
+        let event = ControllerEvent::Layer(command.encode());
+        match &publisher {
+            Some(publisher) => publisher.publish(event).await,
+            None => CONTROLLER_CHANNEL
+                .immediate_publisher()
+                .publish_immediate(event),
+        }
+    }
+}
+
+/// Restarts the breathing effect at every period while it's on, here and on the peripheral, so the
+/// halves pulse in step even though their clocks drift apart.
+#[cfg(feature = "rgb")]
+pub(crate) async fn run_breathing_sync() -> ! {
+    loop {
+        Timer::after(Duration::from_millis(BREATHING_PERIOD_MS as u64)).await;
+        if lighting_effect() == LightingEffect::Breathing {
+            restart_breathing();
+            send_link_command(LinkCommand::RestartBreathing).await;
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, watch::Watch};
+use embassy_time::Instant;
+use rmk::debounce::{DebounceState, DebouncerTrait};
+use rmk::matrix::KeyState;
+
+use crate::boot::KeyPosition;
+
+// A debounced press this soon after the same key was released is counted as chatter. Faster than
+// anyone can deliberately double tap, but slower than a worn switch bouncing past the debouncer.
+const CHATTER_WINDOW_MS: u32 = 40;
+
+/// The key on this half that has chattered the most so far.
+#[cfg_attr(not(feature = "display"), allow(dead_code))] // shown on the OLED
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ChatterReport {
+    pub row: u8,
+    pub col: u8,
+    pub count: u16,
+}
+
+pub(crate) static CHATTER_REPORT: Watch<CriticalSectionRawMutex, ChatterReport, 2> = Watch::new();
+
+/// Wraps a debouncer to count presses that follow a release of the same key suspiciously quickly.
+pub(crate) struct ChatterDetector<D, const INPUT_PIN_NUM: usize, const OUTPUT_PIN_NUM: usize> {
+    debouncer: D,
+    released_at: [[Option<u32>; OUTPUT_PIN_NUM]; INPUT_PIN_NUM],
+    counts: [[u16; OUTPUT_PIN_NUM]; INPUT_PIN_NUM],
+    worst: Option<ChatterReport>,
+}
+
+impl<D, const INPUT_PIN_NUM: usize, const OUTPUT_PIN_NUM: usize>
+    ChatterDetector<D, INPUT_PIN_NUM, OUTPUT_PIN_NUM>
+{
+    pub(crate) fn new(debouncer: D) -> Self {
+        Self {
+            debouncer,
+            released_at: [[None; OUTPUT_PIN_NUM]; INPUT_PIN_NUM],
+            counts: [[0; OUTPUT_PIN_NUM]; INPUT_PIN_NUM],
+            worst: None,
+        }
+    }
+
+    fn record_chatter(&mut self, in_idx: usize, out_idx: usize) {
+        let count = &mut self.counts[in_idx][out_idx];
+        *count = count.saturating_add(1);
+        let key = KeyPosition::from_pins(in_idx, out_idx);
+        log::warn!(
+            "Chatter on row {}, col {} ({} times)",
+            key.row,
+            key.col,
+            *count
+        );
+
+        let report = ChatterReport {
+            row: key.row as u8,
+            col: key.col as u8,
+            count: *count,
+        };
+        if self.worst.is_none_or(|worst| report.count > worst.count) {
+            self.worst = Some(report);
+            CHATTER_REPORT.sender().send(report);
+        }
+    }
+}
+
+impl<D, const INPUT_PIN_NUM: usize, const OUTPUT_PIN_NUM: usize>
+    DebouncerTrait<INPUT_PIN_NUM, OUTPUT_PIN_NUM>
+    for ChatterDetector<D, INPUT_PIN_NUM, OUTPUT_PIN_NUM>
+where
+    D: DebouncerTrait<INPUT_PIN_NUM, OUTPUT_PIN_NUM>,
+{
+    fn detect_change_with_debounce(
+        &mut self,
+        in_idx: usize,
+        out_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        let state = self
+            .debouncer
+            .detect_change_with_debounce(in_idx, out_idx, pin_state, key_state);
+        if let DebounceState::Debounced = state {
+            let now = Instant::now().as_millis() as u32;
+            if !pin_state {
+                self.released_at[in_idx][out_idx] = Some(now);
+            } else if self.released_at[in_idx][out_idx]
+                .is_some_and(|released_at| now.wrapping_sub(released_at) < CHATTER_WINDOW_MS)
+            {
+                self.record_chatter(in_idx, out_idx);
+            }
+        }
+        state
+    }
+}
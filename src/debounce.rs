@@ -0,0 +1,81 @@
+use embassy_time::Instant;
+use lily58_core::debounce::{DebounceAlgorithm, DebounceConfig};
+use portable_atomic::{AtomicU16, Ordering};
+use rmk::debounce::{DebounceState, DebouncerTrait};
+use rmk::matrix::KeyState;
+
+#[cfg(any(
+    all(feature = "debounce-eager-pk", feature = "debounce-defer-pr"),
+    all(feature = "debounce-eager-pk", feature = "debounce-defer-g"),
+    all(feature = "debounce-defer-pr", feature = "debounce-defer-g"),
+))]
+compile_error!("Only one `debounce-*` feature can be enabled");
+
+// Used until a different algorithm is saved with the `DBA` key
+#[cfg(feature = "debounce-eager-pk")]
+const DEFAULT_ALGORITHM: DebounceAlgorithm = DebounceAlgorithm::EagerPerKey;
+#[cfg(feature = "debounce-defer-pr")]
+const DEFAULT_ALGORITHM: DebounceAlgorithm = DebounceAlgorithm::DeferPerRow;
+#[cfg(feature = "debounce-defer-g")]
+const DEFAULT_ALGORITHM: DebounceAlgorithm = DebounceAlgorithm::DeferGlobal;
+#[cfg(not(any(
+    feature = "debounce-eager-pk",
+    feature = "debounce-defer-pr",
+    feature = "debounce-defer-g"
+)))]
+const DEFAULT_ALGORITHM: DebounceAlgorithm = DebounceAlgorithm::DeferPerKey;
+
+pub(crate) const DEFAULT_DEBOUNCE: DebounceConfig = DebounceConfig {
+    algorithm: DEFAULT_ALGORITHM,
+    time_ms: 5,
+};
+
+// Read by the matrix scan on every change, written when the central pushes new settings.
+static DEBOUNCE_CONFIG: AtomicU16 = AtomicU16::new(DEFAULT_DEBOUNCE.to_bits());
+
+pub(crate) fn debounce_config() -> DebounceConfig {
+    DebounceConfig::from_bits(DEBOUNCE_CONFIG.load(Ordering::Relaxed)).unwrap_or(DEFAULT_DEBOUNCE)
+}
+
+pub(crate) fn set_debounce_config(config: DebounceConfig) {
+    DEBOUNCE_CONFIG.store(config.to_bits(), Ordering::Relaxed);
+}
+
+/// A debouncer whose algorithm and time can be changed while the matrix is running.
+pub(crate) struct Debouncer<const INPUT_PIN_NUM: usize, const OUTPUT_PIN_NUM: usize>(
+    lily58_core::debounce::Debouncer<INPUT_PIN_NUM, OUTPUT_PIN_NUM>,
+);
+
+impl<const INPUT_PIN_NUM: usize, const OUTPUT_PIN_NUM: usize>
+    Debouncer<INPUT_PIN_NUM, OUTPUT_PIN_NUM>
+{
+    pub(crate) fn new() -> Self {
+        Self(lily58_core::debounce::Debouncer::new())
+    }
+}
+
+impl<const INPUT_PIN_NUM: usize, const OUTPUT_PIN_NUM: usize>
+    DebouncerTrait<INPUT_PIN_NUM, OUTPUT_PIN_NUM> for Debouncer<INPUT_PIN_NUM, OUTPUT_PIN_NUM>
+{
+    fn detect_change_with_debounce(
+        &mut self,
+        in_idx: usize,
+        out_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        let now = Instant::now().as_millis() as u32;
+        match self.0.update(
+            in_idx,
+            out_idx,
+            pin_state,
+            key_state.pressed,
+            debounce_config(),
+            now,
+        ) {
+            lily58_core::debounce::DebounceState::Ignored => DebounceState::Ignored,
+            lily58_core::debounce::DebounceState::InProgress => DebounceState::InProgress,
+            lily58_core::debounce::DebounceState::Debounced => DebounceState::Debounced,
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use embassy_rp::flash::{Flash, Mode, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+use lily58_core::hid::{MOD_NONE, MOD_SHIFT};
+use rmk::{
+    event::ControllerEvent,
+    heapless::Vec,
+    shifted,
+    types::{
+        action::{Action, KeyAction},
+        keycode::KeyCode,
+        modifier::ModifierCombination,
+    },
+};
+
+use crate::events;
+use crate::flash_config::FLASH_SIZE;
+use crate::keyboard_macros::tap_usage;
+use crate::keymap::{MP1, MP2, MR1, MR2, MST};
+use crate::storage::{SharedFlash, MACRO_OFFSET};
+
+pub(crate) const NUM_DYNAMIC_MACROS: usize = 2;
+const MAX_MACRO_TAPS: usize = 128;
+// Each macro gets an equal share of the sector: a magic number, its number of taps, then modifiers
+// and usage of each tap
+const SLOT_STRIDE: usize = ERASE_SIZE / NUM_DYNAMIC_MACROS;
+const SLOT_MAGIC: [u8; 4] = *b"L58M";
+const SLOT_HEADER: usize = SLOT_MAGIC.len() + 2;
+const SLOT_SIZE: usize = SLOT_HEADER + 2 * MAX_MACRO_TAPS;
+const _: () = assert!(SLOT_SIZE <= SLOT_STRIDE);
+
+const RECORD_KEYS: [KeyAction; NUM_DYNAMIC_MACROS] = [MR1, MR2];
+const PLAY_KEYS: [KeyAction; NUM_DYNAMIC_MACROS] = [MP1, MP2];
+
+// Modifiers of the `shifted!` aliases in the keymap
+const SHIFTED: ModifierCombination = match shifted!(A) {
+    KeyAction::Single(Action::KeyWithModifier(_, modifiers)) => modifiers,
+    _ => panic!("`shifted!` is not a key with modifiers"),
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct MacroTap {
+    modifiers: u8,
+    usage: u8,
+}
+
+type Taps = Vec<MacroTap, MAX_MACRO_TAPS>;
+
+fn slot_offset(slot: usize) -> u32 {
+    MACRO_OFFSET + (slot * SLOT_STRIDE) as u32
+}
+
+fn read_slot<M: Mode>(flash: &mut Flash<'_, FLASH, M, FLASH_SIZE>, slot: usize) -> [u8; SLOT_SIZE] {
+    let mut bytes = [0xFF; SLOT_SIZE];
+    if flash.blocking_read(slot_offset(slot), &mut bytes).is_err() {
+        log::error!("Failed to read dynamic macro {}", slot + 1);
+    }
+    bytes
+}
+
+// The sector sits below the storage region, where older firmware or other data may have been left,
+// so slots without the magic number are empty
+fn load<M: Mode>(flash: &mut Flash<'_, FLASH, M, FLASH_SIZE>, slot: usize) -> Taps {
+    let bytes = read_slot(flash, slot);
+    let len = u16::from_le_bytes([bytes[4], bytes[5]]) as usize;
+    let valid = bytes[..4] == SLOT_MAGIC && len <= MAX_MACRO_TAPS;
+    bytes[SLOT_HEADER..]
+        .chunks_exact(2)
+        .take(if valid { len } else { 0 })
+        .map(|tap| MacroTap {
+            modifiers: tap[0],
+            usage: tap[1],
+        })
+        .collect()
+}
+
+// The sector has to be erased as a whole, so the other macros are written back along with this one
+fn store<M: Mode>(flash: &mut Flash<'_, FLASH, M, FLASH_SIZE>, slot: usize, taps: &Taps) {
+    let mut slots = [[0xFF; SLOT_SIZE]; NUM_DYNAMIC_MACROS];
+    for (i, bytes) in slots.iter_mut().enumerate() {
+        if i != slot {
+            *bytes = read_slot(flash, i);
+        }
+    }
+    let bytes = &mut slots[slot];
+    bytes[..4].copy_from_slice(&SLOT_MAGIC);
+    bytes[4..SLOT_HEADER].copy_from_slice(&(taps.len() as u16).to_le_bytes());
+    for (chunk, tap) in bytes[SLOT_HEADER..].chunks_exact_mut(2).zip(taps) {
+        chunk.copy_from_slice(&[tap.modifiers, tap.usage]);
+    }
+
+    let mut result = flash.blocking_erase(MACRO_OFFSET, MACRO_OFFSET + ERASE_SIZE as u32);
+    for (i, bytes) in slots.iter().enumerate() {
+        result = result.and_then(|_| flash.blocking_write(slot_offset(i), bytes));
+    }
+    if result.is_err() {
+        log::error!("Failed to save dynamic macro {}", slot + 1);
+    }
+}
+
+/// Forgets all recorded macros.
+pub(crate) fn clear<M: Mode>(flash: &mut Flash<'_, FLASH, M, FLASH_SIZE>) {
+    if flash
+        .blocking_erase(MACRO_OFFSET, MACRO_OFFSET + ERASE_SIZE as u32)
+        .is_err()
+    {
+        log::error!("Failed to clear dynamic macros");
+    }
+}
+
+fn modifier_bit(key: KeyCode) -> Option<u8> {
+    let code = key as u16;
+    if (KeyCode::LCtrl as u16..=KeyCode::RGui as u16).contains(&code) {
+        Some(1 << (code - KeyCode::LCtrl as u16))
+    } else {
+        None
+    }
+}
+
+// Only keys of the HID keyboard page can be replayed, media and mouse keys are left out
+fn keyboard_usage(key: KeyCode) -> Option<u8> {
+    let code = key as u16;
+    (KeyCode::A as u16..=KeyCode::F24 as u16)
+        .contains(&code)
+        .then_some(code as u8)
+}
+
+/// Records the keys typed after `MR1`/`MR2` until `MST` or the same record key is pressed, and
+/// saves them to flash. `MP1`/`MP2` type them out again, with the modifiers that were held.
+pub(crate) async fn run_dynamic_macros(flash: &SharedFlash) -> ! {
+    let mut subscriber = events::subscribe("dynamic macros").await;
+    let mut recording: Option<(usize, Taps)> = None;
+    let mut modifiers = MOD_NONE;
+    loop {
+        let ControllerEvent::Key(event, action) = subscriber.next_message_pure().await else {
+            continue;
+        };
+
+        if let Some(slot) = RECORD_KEYS.iter().position(|key| *key == action) {
+            if event.pressed {
+                match recording.take() {
+                    Some((recorded_slot, taps)) => {
+                        store(&mut *flash.lock().await, recorded_slot, &taps)
+                    }
+                    None => recording = Some((slot, Vec::new())),
+                }
+            }
+            continue;
+        }
+        if action == MST {
+            if event.pressed {
+                if let Some((slot, taps)) = recording.take() {
+                    store(&mut *flash.lock().await, slot, &taps);
+                }
+            }
+            continue;
+        }
+        if let Some(slot) = PLAY_KEYS.iter().position(|key| *key == action) {
+            // Playing back while recording would record nothing, the taps bypass the keymap
+            if event.pressed && recording.is_none() {
+                let taps = load(&mut *flash.lock().await, slot);
+                for tap in taps {
+                    tap_usage(tap.modifiers, tap.usage).await;
+                }
+            }
+            continue;
+        }
+
+        let tap = match action {
+            KeyAction::Single(Action::Key(key)) => {
+                if let Some(bit) = modifier_bit(key) {
+                    if event.pressed {
+                        modifiers |= bit;
+                    } else {
+                        modifiers &= !bit;
+                    }
+                    continue;
+                }
+                keyboard_usage(key).map(|usage| MacroTap { modifiers, usage })
+            }
+            KeyAction::Single(Action::KeyWithModifier(key, extra)) if extra == SHIFTED => {
+                keyboard_usage(key).map(|usage| MacroTap {
+                    modifiers: modifiers | MOD_SHIFT,
+                    usage,
+                })
+            }
+            _ => None,
+        };
+        let full = match (tap, recording.as_mut()) {
+            (Some(tap), Some((_, taps))) if event.pressed => taps.push(tap).is_err(),
+            _ => false,
+        };
+        if full {
+            let (slot, taps) = recording.take().unwrap();
+            log::warn!("Dynamic macro {} is full, stopping the recording", slot + 1);
+            store(&mut *flash.lock().await, slot, &taps);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pubsub::{PubSubChannel, Subscriber};
+use rmk::channel::CONTROLLER_CHANNEL;
+use rmk::event::ControllerEvent;
+
+// RMK's `CONTROLLER_CHANNEL` has a fixed number of subscriber slots, shared with RMK's own
+// controllers, and taking one more than it has fails. The firmware only takes a single slot, in
+// `run_event_forwarder`, and hands the events on to its own tasks through `EVENTS`, which has room
+// for all of them.
+//
+// One slot per task calling `subscribe`. The central has the most: state sync, central sync,
+// settings keys, one-shot lock, auto-shift, Caps Word, leader, dynamic macros, text macros and
+// mouse keys.
+const SUBSCRIBERS: usize = 10;
+const QUEUE_SIZE: usize = 8;
+
+pub(crate) type EventSubscriber =
+    Subscriber<'static, CriticalSectionRawMutex, ControllerEvent, QUEUE_SIZE, SUBSCRIBERS, 0>;
+
+static EVENTS: PubSubChannel<CriticalSectionRawMutex, ControllerEvent, QUEUE_SIZE, SUBSCRIBERS, 0> =
+    PubSubChannel::new();
+
+/// Subscribes a task to the events RMK publishes on `CONTROLLER_CHANNEL`. If there's no slot left
+/// the error is logged and this never returns, so the keyboard keeps working without the task.
+pub(crate) async fn subscribe(task: &str) -> EventSubscriber {
+    match EVENTS.subscriber() {
+        Ok(subscriber) => subscriber,
+        Err(_) => {
+            log::error!("No event subscriber left for {}", task);
+            core::future::pending().await
+        }
+    }
+}
+
+/// Hands every event from `CONTROLLER_CHANNEL` on to the subscribed tasks. A task that falls behind
+/// misses the oldest events instead of holding up the others, as with RMK's own channel.
+pub(crate) async fn run_event_forwarder() -> ! {
+    let mut subscriber = match CONTROLLER_CHANNEL.subscriber() {
+        Ok(subscriber) => subscriber,
+        Err(_) => {
+            log::error!("No controller channel subscriber left for the firmware's tasks");
+            core::future::pending().await
+        }
+    };
+    let publisher = EVENTS.immediate_publisher();
+    loop {
+        publisher.publish_immediate(subscriber.next_message_pure().await);
+    }
+}
@@ -0,0 +1,67 @@
+use embassy_rp::flash::{self, Flash, Mode, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+use lily58_core::config::{self, BoardConfig, ConfigSector};
+
+use crate::debounce::DEFAULT_DEBOUNCE;
+
+// `FLASH_SIZE` and `STORAGE_OFFSET`, set per board at build time
+include!(concat!(env!("OUT_DIR"), "/flash_layout.rs"));
+
+// Board settings that RMK's storage has no record for are kept in the first sector of the storage
+// region, followed by RMK's storage.
+//
+// Dynamic macros get the sector just below the storage region, so the sectors above keep the
+// addresses they had before macros were added and boards upgrade without losing their keymap. RMK's
+// storage only persists the records it defines itself, and the only one that could hold them is the
+// Vial macro buffer, where recordings would overwrite the macros edited in Vial. So the macros are
+// written to a sector of their own, through the same flash handle as RMK's storage.
+const CONFIG_OFFSET: u32 = STORAGE_OFFSET as u32;
+
+/// Settings used until others are saved.
+pub(crate) const DEFAULT_CONFIG: BoardConfig = BoardConfig {
+    debounce: DEFAULT_DEBOUNCE,
+    layout: None,
+};
+
+/// The sector holding the board settings.
+pub(crate) struct ConfigFlash<'a, 'd, M: Mode>(pub &'a mut Flash<'d, FLASH, M, FLASH_SIZE>);
+
+impl<M: Mode> ConfigSector for ConfigFlash<'_, '_, M> {
+    type Error = flash::Error;
+
+    fn read(&mut self, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.blocking_read(CONFIG_OFFSET, bytes)
+    }
+
+    fn erase(&mut self) -> Result<(), Self::Error> {
+        self.0
+            .blocking_erase(CONFIG_OFFSET, CONFIG_OFFSET + ERASE_SIZE as u32)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.0.blocking_write(CONFIG_OFFSET, bytes)
+    }
+}
+
+/// Reads the board settings, falling back to the defaults if none were saved yet.
+pub(crate) fn load<M: Mode>(flash: &mut Flash<'_, FLASH, M, FLASH_SIZE>) -> BoardConfig {
+    config::read(&mut ConfigFlash(flash)).unwrap_or(DEFAULT_CONFIG)
+}
+
+pub(crate) fn store<M: Mode>(
+    flash: &mut Flash<'_, FLASH, M, FLASH_SIZE>,
+    board_config: BoardConfig,
+) {
+    if config::store(&mut ConfigFlash(flash), board_config).is_err() {
+        log::error!("Failed to save board config");
+    }
+}
+
+pub(crate) fn update<M: Mode>(
+    flash: &mut Flash<'_, FLASH, M, FLASH_SIZE>,
+    f: impl FnOnce(&mut BoardConfig),
+) {
+    if config::update(&mut ConfigFlash(flash), DEFAULT_CONFIG, f).is_err() {
+        log::error!("Failed to save board config");
+    }
+}
@@ -0,0 +1,101 @@
+use portable_atomic::{AtomicBool, Ordering};
+use rmk::debounce::{DebounceState, DebouncerTrait};
+use rmk::matrix::KeyState;
+
+// Set when the key last pressed on this half should be locked, taken by the matrix scan
+static LOCK_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Lock {
+    /// Still down from the tap that locked it
+    Held,
+    /// Physically up, but still pressed as far as RMK knows
+    Released,
+    /// Pressed again, its release unlocks it
+    PressedAgain,
+}
+
+/// Has the matrix scan lock the key last pressed on this half, if it's still down.
+pub(crate) fn lock_last_pressed() {
+    LOCK_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Wraps a debouncer to hold a locked key down: RMK doesn't see its release until the key is
+/// pressed and released once more, so it gets a regular press and release, just a long one.
+pub(crate) struct KeyLock<D> {
+    debouncer: D,
+    last_pressed: Option<(usize, usize)>,
+    locked: Option<((usize, usize), Lock)>,
+}
+
+impl<D> KeyLock<D> {
+    pub(crate) fn new(debouncer: D) -> Self {
+        Self {
+            debouncer,
+            last_pressed: None,
+            locked: None,
+        }
+    }
+}
+
+impl<D, const INPUT_PIN_NUM: usize, const OUTPUT_PIN_NUM: usize>
+    DebouncerTrait<INPUT_PIN_NUM, OUTPUT_PIN_NUM> for KeyLock<D>
+where
+    D: DebouncerTrait<INPUT_PIN_NUM, OUTPUT_PIN_NUM>,
+{
+    fn detect_change_with_debounce(
+        &mut self,
+        in_idx: usize,
+        out_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        let key = (in_idx, out_idx);
+        // A request with no key to lock, or for a key that's already up, is dropped
+        if self.last_pressed.is_none_or(|last| last == key)
+            && LOCK_REQUESTED.swap(false, Ordering::Relaxed)
+            && self.last_pressed.is_some()
+            && key_state.pressed
+        {
+            self.locked = Some((key, Lock::Held));
+        }
+
+        let Some((_, lock)) = self.locked.filter(|(locked, _)| *locked == key) else {
+            let state = self
+                .debouncer
+                .detect_change_with_debounce(in_idx, out_idx, pin_state, key_state);
+            if let DebounceState::Debounced = state {
+                if pin_state {
+                    self.last_pressed = Some(key);
+                }
+            }
+            return state;
+        };
+
+        // The wrapped debouncer follows the switch rather than what RMK was told
+        let switch = KeyState {
+            pressed: lock != Lock::Released,
+            ..*key_state
+        };
+        match self
+            .debouncer
+            .detect_change_with_debounce(in_idx, out_idx, pin_state, &switch)
+        {
+            DebounceState::Debounced => match lock {
+                Lock::Held => {
+                    self.locked = Some((key, Lock::Released));
+                    DebounceState::Ignored
+                }
+                Lock::Released => {
+                    self.locked = Some((key, Lock::PressedAgain));
+                    DebounceState::Ignored
+                }
+                Lock::PressedAgain => {
+                    self.locked = None;
+                    DebounceState::Debounced
+                }
+            },
+            state => state,
+        }
+    }
+}
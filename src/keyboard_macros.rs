@@ -1,13 +1,26 @@
+use lily58_core::{
+    hid::{MOD_ALT, MOD_CTRL, MOD_NONE},
+    leader::validate_leader_sequences,
+    text::{text_taps, HostLayout, UnicodeMode},
+};
 use rmk::{
+    channel::KEYBOARD_REPORT_CHANNEL,
     config::ForksConfig,
+    event::ControllerEvent,
     fork::{Fork, StateBits},
     heapless::Vec,
+    hid::Report,
     k,
     types::{
-        action::KeyAction, led_indicator::LedIndicator, modifier::ModifierCombination,
-        mouse_button::MouseButtons,
+        action::KeyAction, keycode::KeyCode, led_indicator::LedIndicator,
+        modifier::ModifierCombination, mouse_button::MouseButtons,
     },
 };
+use usbd_hid::descriptor::KeyboardReport;
+
+use crate::events;
+use crate::keymap::{TX1, TX2};
+use crate::leader::{LeaderAction, LeaderSequence};
 
 fn shift_override(action: KeyAction, override_action: KeyAction) -> Fork {
     Fork::new(
@@ -25,6 +38,8 @@ fn shift_override(action: KeyAction, override_action: KeyAction) -> Fork {
     )
 }
 
+// Caps Word only shifts the letters themselves, so these overrides don't fire while it's active and
+// Backspace still deletes the previous character.
 pub(crate) fn get_forks() -> ForksConfig {
     ForksConfig {
         forks: Vec::from_slice(&[
@@ -35,3 +50,66 @@ pub(crate) fn get_forks() -> ForksConfig {
         .expect("Some fork is not valid"),
     }
 }
+
+// Letters and digits typed after `LDR`, as found on the base layer, and what they do
+pub(crate) const LEADER_SEQUENCES: &[LeaderSequence] = &[
+    LeaderSequence::new(b"q", LeaderAction::Tap(MOD_ALT, KeyCode::F4)),
+    LeaderSequence::new(b"s", LeaderAction::Tap(MOD_CTRL, KeyCode::S)),
+    LeaderSequence::new(b"td", LeaderAction::Text("TODO: ")),
+    LeaderSequence::new(b"lg", LeaderAction::Text("Looks good to me!")),
+    LeaderSequence::new(b"ba", LeaderAction::Text("#!/usr/bin/env bash\n")),
+    LeaderSequence::new(b"us", LeaderAction::Text("¯\\_(ツ)_/¯")),
+];
+const _: () = validate_leader_sequences(LEADER_SEQUENCES);
+
+// How the host turns keys into characters, text is typed to match
+const HOST_LAYOUT: HostLayout = HostLayout::Us;
+const UNICODE_MODE: UnicodeMode = UnicodeMode::Linux;
+
+// Text typed by a key
+const TEXT_MACROS: &[(KeyAction, &str)] = &[
+    (TX1, "Best regards,\nThe Lily58 team\n"),
+    (TX2, "#[derive(Clone, Copy, Debug, PartialEq, Eq)]\n"),
+];
+
+/// Taps a key with the given modifiers straight to the host, for behaviors that type something
+/// the keymap doesn't. Keys held at the time are released until RMK sends its next report.
+pub(crate) async fn tap_key(modifiers: u8, key: KeyCode) {
+    tap_usage(modifiers, key as u16 as u8).await;
+}
+
+pub(crate) async fn tap_usage(modifiers: u8, usage: u8) {
+    for (modifier, usage) in [(modifiers, usage), (MOD_NONE, 0)] {
+        let report = KeyboardReport {
+            modifier,
+            reserved: 0,
+            leds: 0,
+            keycodes: [usage, 0, 0, 0, 0, 0],
+        };
+        KEYBOARD_REPORT_CHANNEL
+            .send(Report::KeyboardReport(report))
+            .await;
+    }
+}
+
+/// Types text for `HOST_LAYOUT`, falling back to `UNICODE_MODE` for characters it has no key for.
+pub(crate) async fn type_text(text: &str) {
+    for (modifiers, usage) in text_taps(HOST_LAYOUT, UNICODE_MODE, text) {
+        tap_usage(modifiers, usage).await;
+    }
+}
+
+/// Types the text macro bound to a key when it's pressed.
+pub(crate) async fn run_text_macros() -> ! {
+    let mut subscriber = events::subscribe("text macros").await;
+    loop {
+        if let ControllerEvent::Key(event, action) = subscriber.next_message_pure().await {
+            if let Some((_, text)) = TEXT_MACROS
+                .iter()
+                .find(|(key, _)| event.pressed && *key == action)
+            {
+                type_text(text).await;
+            }
+        }
+    }
+}
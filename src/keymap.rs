@@ -1,8 +1,28 @@
 #![allow(dead_code)] // macros are treated as dead code sometimes
-use rmk::{a, k, layer, mo, shifted, types::action::KeyAction};
+use embassy_time::Duration;
+use lily58_core::tap_dance::TAPPING_TERM_MS;
+use rmk::{
+    a,
+    config::TapDanceConfig,
+    heapless::Vec,
+    k, layer, mo, osl, osm, shifted,
+    tap_dance::TapDance,
+    td, tg,
+    types::{
+        action::{Action, EncoderAction, KeyAction},
+        keycode::KeyCode,
+        modifier::ModifierCombination,
+    },
+};
 pub(crate) const COLS: usize = 6;
 pub(crate) const ROWS: usize = 5;
 
+// Arguments are right, gui, alt, shift, ctrl
+const CTRL: ModifierCombination = ModifierCombination::new_from(false, false, false, false, true);
+const SHIFT: ModifierCombination = ModifierCombination::new_from(false, false, false, true, false);
+const ALT: ModifierCombination = ModifierCombination::new_from(false, false, true, false, false);
+const GUI: ModifierCombination = ModifierCombination::new_from(false, true, false, false, false);
+
 // 3-character-wide key action aliases
 const ___: KeyAction = a!(Transparent);
 const _0_: KeyAction = k!(Kc0);
@@ -42,6 +62,7 @@ const _X_: KeyAction = k!(X);
 const _Y_: KeyAction = k!(Y);
 const _Z_: KeyAction = k!(Z);
 const AMP: KeyAction = shifted!(Kc7);
+pub(crate) const ASH: KeyAction = tg!(AUTO_SHIFT_LAYER as u8); // auto-shift
 const AST: KeyAction = shifted!(Kc8);
 const AT_: KeyAction = shifted!(Kc2);
 const BLO: KeyAction = k!(Bootloader);
@@ -51,6 +72,10 @@ const BSP: KeyAction = k!(Backspace);
 const BTK: KeyAction = k!(Grave);
 const COM: KeyAction = k!(Comma);
 const CRC: KeyAction = shifted!(Kc6);
+pub(crate) const CWD: KeyAction = k!(User5); // Caps Word
+pub(crate) const DBA: KeyAction = k!(User2); // next debounce algorithm
+pub(crate) const DBD: KeyAction = k!(User0); // debounce time down
+pub(crate) const DBU: KeyAction = k!(User1); // debounce time up
 const DEL: KeyAction = k!(Delete);
 const DLR: KeyAction = shifted!(Kc4);
 const DOT: KeyAction = k!(Dot);
@@ -59,6 +84,7 @@ const END: KeyAction = k!(End);
 const ENT: KeyAction = k!(Enter);
 const EQL: KeyAction = k!(Equal);
 const ESC: KeyAction = k!(Escape);
+const ESD: KeyAction = td!(0); // Escape tap dance
 const F01: KeyAction = k!(F1);
 const F02: KeyAction = k!(F2);
 const F03: KeyAction = k!(F3);
@@ -89,14 +115,36 @@ const HSH: KeyAction = shifted!(Kc3);
 const LAL: KeyAction = k!(LAlt);
 const LCB: KeyAction = shifted!(LeftBracket);
 const LCT: KeyAction = k!(LCtrl);
+pub(crate) const LDR: KeyAction = tg!(LEADER_LAYER as u8); // leader key
 const LFT: KeyAction = k!(Left);
 const LGU: KeyAction = k!(LGui);
-const LOW: KeyAction = mo!(1);
+pub(crate) const LMD: KeyAction = k!(User4); // next underglow effect
+const LOW: KeyAction = mo!(LOWER_LAYER as u8);
 const LPR: KeyAction = shifted!(Kc9);
 const LSB: KeyAction = k!(LeftBracket);
 const LSH: KeyAction = k!(LShift);
+pub(crate) const MB1: KeyAction = k!(User19); // left mouse button
+pub(crate) const MB2: KeyAction = k!(User20); // right mouse button
+pub(crate) const MB3: KeyAction = k!(User21); // middle mouse button
 const MNS: KeyAction = k!(Minus);
+pub(crate) const MP1: KeyAction = k!(User9); // play dynamic macro 1
+pub(crate) const MP2: KeyAction = k!(User10); // play dynamic macro 2
+pub(crate) const MR1: KeyAction = k!(User6); // record dynamic macro 1
+pub(crate) const MR2: KeyAction = k!(User7); // record dynamic macro 2
+pub(crate) const MSD: KeyAction = k!(User14); // mouse down
+const MSE: KeyAction = tg!(MOUSE_LAYER as u8); // mouse layer
+pub(crate) const MSL: KeyAction = k!(User15); // mouse left
+pub(crate) const MSR: KeyAction = k!(User16); // mouse right
+pub(crate) const MST: KeyAction = k!(User8); // stop recording
+pub(crate) const MSU: KeyAction = k!(User13); // mouse up
 const NXT: KeyAction = k!(MediaNextTrack);
+pub(crate) const OAL: KeyAction = osm!(ALT); // one-shot alt
+pub(crate) const OCT: KeyAction = osm!(CTRL); // one-shot ctrl
+pub(crate) const OGU: KeyAction = osm!(GUI); // one-shot gui
+pub(crate) const OLW: KeyAction = osl!(LOWER_LAYER as u8); // one-shot lower
+pub(crate) const ORA: KeyAction = osl!(RAISE_LAYER as u8); // one-shot raise
+pub(crate) const OSH: KeyAction = osm!(SHIFT); // one-shot shift
+pub(crate) const PBL: KeyAction = k!(User3); // peripheral bootloader
 const PCT: KeyAction = shifted!(Kc5);
 const PGD: KeyAction = k!(PageDown);
 const PGU: KeyAction = k!(PageUp);
@@ -106,7 +154,7 @@ const PLY: KeyAction = k!(MediaPlayPause);
 const PRT: KeyAction = k!(PrintScreen);
 const PRV: KeyAction = k!(MediaPrevTrack);
 const QUO: KeyAction = k!(Quote);
-const RAI: KeyAction = mo!(2);
+const RAI: KeyAction = mo!(RAISE_LAYER as u8);
 const RCB: KeyAction = shifted!(RightBracket);
 const RGT: KeyAction = k!(Right);
 const RGU: KeyAction = k!(RGui);
@@ -117,12 +165,57 @@ const SCN: KeyAction = k!(Semicolon);
 const SLS: KeyAction = k!(Slash);
 const SPC: KeyAction = k!(Space);
 const TAB: KeyAction = k!(Tab);
+pub(crate) const TX1: KeyAction = k!(User11); // text macro 1
+pub(crate) const TX2: KeyAction = k!(User12); // text macro 2
 const UP_: KeyAction = k!(Up);
 const VLD: KeyAction = k!(AudioVolDown);
 const VLU: KeyAction = k!(AudioVolUp);
+pub(crate) const WHD: KeyAction = k!(User18); // wheel down
+pub(crate) const WHU: KeyAction = k!(User17); // wheel up
 const XXX: KeyAction = a!(No);
 
-pub const NUM_LAYERS: usize = 3;
+// How long a tap dance waits for the next tap, or for a key to count as held
+const TAPPING_TERM: Duration = Duration::from_millis(TAPPING_TERM_MS as u64);
+
+// Tap dances by their `td!` index: tap, hold, hold after a tap, double tap
+pub(crate) fn get_tap_dances() -> TapDanceConfig {
+    TapDanceConfig {
+        tap_dances: Vec::from_slice(&[
+            // `ESD`: Escape, Caps Lock on a double tap, Lower while held
+            TapDance::new_from_vial(
+                Action::Key(KeyCode::Escape),
+                Action::LayerOn(LOWER_LAYER as u8),
+                Action::No,
+                Action::Key(KeyCode::CapsLock),
+                TAPPING_TERM,
+            ),
+        ])
+        .expect("Too many tap dances"),
+    }
+}
+
+pub const NUM_LAYERS: usize = 7;
+// One encoder on each half, the central's first
+pub const NUM_ENCODERS: usize = 2;
+pub const LAYER_NAMES: [&str; NUM_LAYERS] = [
+    "Base",
+    "Auto-shift",
+    "Caps Word",
+    "Lower",
+    "Raise",
+    "Leader",
+    "Mouse",
+];
+pub(crate) const BASE_LAYER: usize = 0;
+// Sits right above the base layer, so the layers on top of it still work as usual. Filled by the
+// auto-shift handler, the compiled layer is left transparent.
+pub(crate) const AUTO_SHIFT_LAYER: usize = 1;
+// Filled and turned on by the Caps Word handler, the compiled layer is left transparent
+pub(crate) const CAPS_WORD_LAYER: usize = 2;
+pub(crate) const LOWER_LAYER: usize = 3;
+pub(crate) const RAISE_LAYER: usize = 4;
+pub(crate) const LEADER_LAYER: usize = 5;
+pub(crate) const MOUSE_LAYER: usize = 6;
 
 // Internally the peripheral board is flipped and treated like a vertical extension of the first board.
 // This macro allows us to specify the keymap in an order that matches the physical layout, since the
@@ -157,26 +250,75 @@ macro_rules! lily_layer {
 
 pub const fn get_default_keymap() -> [[[KeyAction; COLS]; ROWS * 2]; NUM_LAYERS] {
     [
-        lily_layer!(
-            ESC _1_ _2_ _3_ _4_ _5_         _6_ _7_ _8_ _9_ _0_ EQL
-            TAB _Q_ _W_ _E_ _R_ _T_         _Y_ _U_ _I_ _O_ _P_ MNS
-            LSH _A_ _S_ _D_ _F_ _G_         _H_ _J_ _K_ _L_ SCN QUO
-            LCT _Z_ _X_ _C_ _V_ _B_ HOM PGU _N_ _M_ COM DOT SLS RSH
-                        LAL LGU LOW SPC ENT RAI BSP RGU
-        ),
+        base_layer(),
+        [[___; COLS]; ROWS * 2],
+        [[___; COLS]; ROWS * 2],
         lily_layer!(
             F01 F02 F03 F04 F05 F06         F07 F08 F09 F10 F11 F12
-            TAB XXX XXX XXX XXX XXX         XXX XXX XXX XXX XXX MNS
+            TAB LMD CWD MR1 MR2 MST         PBL TX1 DBA DBD DBU MNS
             LSH BNG AT_ HSH DLR PCT         CRC AMP AST LPR RPR BSL
-            LCT XXX XXX XXX XXX BTK END PGD GRV LSB RSB LCB RCB PIP
+            LCT LDR MP1 MP2 TX2 BTK END PGD GRV LSB RSB LCB RCB PIP
                         LAL LGU LOW BLO ENT RAI DEL RGU
         ),
         lily_layer!(
             F13 F14 F15 F16 F17 F18         F19 F20 F21 F22 F23 F24
-            ___ PLS MNS AST SLS EQL         PLY PRV VLD VLU NXT PRT
-            ___ _1_ _2_ _3_ _4_ _5_         HOM LFT DWN UP_ RGT END
-            ___ _6_ _7_ _8_ _9_ _0_ DOT LPR _N_ _M_ COM DOT SLS RSH
-                        ___ ___ ___ SPC ___ ___ BSP ___
+            ASH PLS MNS AST SLS EQL         PLY PRV VLD VLU NXT PRT
+            OSH _1_ _2_ _3_ _4_ _5_         HOM LFT DWN UP_ RGT END
+            OCT _6_ _7_ _8_ _9_ _0_ DOT LPR OLW ORA COM DOT SLS RSH
+                        OAL OGU MSE SPC ___ ___ BSP ___
+        ),
+        leader_layer(),
+        lily_layer!(
+            ___ XXX XXX XXX XXX XXX         XXX XXX XXX XXX XXX XXX
+            ___ XXX XXX MSU XXX WHU         XXX XXX XXX XXX XXX XXX
+            ___ XXX MSL MSD MSR WHD         XXX MB1 MB3 MB2 XXX XXX
+            ___ XXX XXX XXX XXX XXX XXX XXX XXX XXX XXX XXX XXX ___
+                        ___ ___ MSE MB1 MB2 ___ ___ ___
         ),
     ]
 }
+
+const fn base_layer() -> [[KeyAction; COLS]; ROWS * 2] {
+    lily_layer!(
+        ESD _1_ _2_ _3_ _4_ _5_         _6_ _7_ _8_ _9_ _0_ EQL
+        TAB _Q_ _W_ _E_ _R_ _T_         _Y_ _U_ _I_ _O_ _P_ MNS
+        LSH _A_ _S_ _D_ _F_ _G_         _H_ _J_ _K_ _L_ SCN QUO
+        LCT _Z_ _X_ _C_ _V_ _B_ HOM PGU _N_ _M_ COM DOT SLS RSH
+                    LAL LGU LOW SPC ENT RAI BSP RGU
+    )
+}
+
+// Where auto-shift applies to the key on the base layer, in matrix order: the rows of the left
+// half, then those of the right half.
+const AUTO_SHIFT_ROW: [bool; COLS] = [true; COLS];
+const THUMB_ROW: [bool; COLS] = [false; COLS];
+pub(crate) const AUTO_SHIFT_POSITIONS: [[bool; COLS]; ROWS * 2] = [
+    AUTO_SHIFT_ROW,
+    AUTO_SHIFT_ROW,
+    AUTO_SHIFT_ROW,
+    AUTO_SHIFT_ROW,
+    THUMB_ROW,
+    AUTO_SHIFT_ROW,
+    AUTO_SHIFT_ROW,
+    AUTO_SHIFT_ROW,
+    AUTO_SHIFT_ROW,
+    THUMB_ROW,
+];
+
+// Swallows the keys typed after `LDR`, the leader handler looks them up on the base layer instead
+const fn leader_layer() -> [[KeyAction; COLS]; ROWS * 2] {
+    [[XXX; COLS]; ROWS * 2]
+}
+
+// Clockwise and counter-clockwise actions for each encoder, per layer
+pub const fn get_default_encoder_map() -> [[EncoderAction; NUM_ENCODERS]; NUM_LAYERS] {
+    [
+        [EncoderAction::new(VLU, VLD), EncoderAction::new(PGD, PGU)],
+        [EncoderAction::new(___, ___), EncoderAction::new(___, ___)],
+        [EncoderAction::new(___, ___), EncoderAction::new(___, ___)],
+        [EncoderAction::new(NXT, PRV), EncoderAction::new(END, HOM)],
+        [EncoderAction::new(RGT, LFT), EncoderAction::new(DWN, UP_)],
+        [EncoderAction::new(XXX, XXX), EncoderAction::new(XXX, XXX)],
+        [EncoderAction::new(WHD, WHU), EncoderAction::new(WHD, WHU)],
+    ]
+}
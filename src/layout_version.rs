@@ -0,0 +1,96 @@
+use embassy_rp::flash::{Flash, Mode};
+use embassy_rp::peripherals::FLASH;
+use lily58_core::config;
+use lily58_core::layout::{Fnv1a, LayoutCheck, LayoutFingerprint};
+use rmk::types::action::{Action, KeyAction};
+
+use crate::flash_config::{ConfigFlash, DEFAULT_CONFIG, FLASH_SIZE};
+use crate::keymap::{get_default_keymap, COLS, NUM_LAYERS, ROWS};
+
+// Actions are hashed by a tag and their fields, rather than through `Debug` or their memory
+// layout, which can change with RMK or the compiler without the keymap changing. Actions without
+// a tag of their own only count by position.
+const OTHER: u8 = 0xFF;
+
+fn hash_action(hasher: &mut Fnv1a, action: Action) {
+    match action {
+        Action::No => hasher.write(&[0]),
+        Action::Transparent => hasher.write(&[1]),
+        Action::Key(key) => {
+            hasher.write(&[2]);
+            hasher.write(&(key as u16).to_le_bytes());
+        }
+        Action::KeyWithModifier(key, modifiers) => {
+            hasher.write(&[3]);
+            hasher.write(&(key as u16).to_le_bytes());
+            hasher.write(&[modifiers.into_bits()]);
+        }
+        Action::Modifier(modifiers) => hasher.write(&[4, modifiers.into_bits()]),
+        Action::LayerOn(layer) => hasher.write(&[5, layer]),
+        Action::LayerToggle(layer) => hasher.write(&[6, layer]),
+        _ => hasher.write(&[OTHER]),
+    }
+}
+
+fn hash_key_action(hasher: &mut Fnv1a, action: KeyAction) {
+    match action {
+        KeyAction::No => hasher.write(&[0]),
+        KeyAction::Transparent => hasher.write(&[1]),
+        KeyAction::Single(action) => {
+            hasher.write(&[2]);
+            hash_action(hasher, action);
+        }
+        KeyAction::TapHold(tap, hold) => {
+            hasher.write(&[3]);
+            hash_action(hasher, tap);
+            hash_action(hasher, hold);
+        }
+        KeyAction::TapDance(index) => hasher.write(&[4, index]),
+        KeyAction::OneShot(action) => {
+            hasher.write(&[5]);
+            hash_action(hasher, action);
+        }
+        _ => hasher.write(&[OTHER]),
+    }
+}
+
+/// Fingerprint of the default keymap this firmware was built with.
+pub(crate) fn current_layout() -> LayoutFingerprint {
+    let mut hasher = Fnv1a::new();
+    for layer in get_default_keymap() {
+        for row in layer {
+            for action in row {
+                hash_key_action(&mut hasher, action);
+            }
+        }
+    }
+    LayoutFingerprint {
+        layers: NUM_LAYERS as u8,
+        rows: (ROWS * 2) as u8,
+        cols: COLS as u8,
+        keymap_hash: hasher.finish(),
+    }
+}
+
+/// Checks whether RMK's storage was written for a different layout and has to be cleared, without
+/// recording the current one yet, see `record_layout`.
+pub(crate) fn check_layout<M: Mode>(flash: &mut Flash<'_, FLASH, M, FLASH_SIZE>) -> LayoutCheck {
+    let check = LayoutCheck::new(config::read(&mut ConfigFlash(flash)), current_layout());
+    if check.changed() {
+        log::warn!("Keymap layout changed, clearing storage");
+    }
+    check
+}
+
+/// Records the current layout, once RMK's storage has been cleared for it.
+pub(crate) fn record_layout<M: Mode>(
+    flash: &mut Flash<'_, FLASH, M, FLASH_SIZE>,
+    check: LayoutCheck,
+) {
+    if check
+        .record(&mut ConfigFlash(flash), DEFAULT_CONFIG)
+        .is_err()
+    {
+        log::error!("Failed to save keymap layout");
+    }
+}
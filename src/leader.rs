@@ -0,0 +1,100 @@
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use lily58_core::leader::{key_char, Leader, Step};
+use rmk::{
+    event::ControllerEvent,
+    types::{
+        action::{Action, KeyAction},
+        keycode::KeyCode,
+    },
+};
+
+use crate::events;
+use crate::keyboard_macros::{tap_key, type_text, LEADER_SEQUENCES};
+use crate::keymap::{LDR, LEADER_LAYER};
+use crate::live_keymap::{base_action, set_layer, LiveKeymap};
+
+// The whole sequence has to be typed within this time of pressing `LDR`
+const LEADER_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum LeaderAction {
+    /// Tap a key with the given HID modifier bits
+    Tap(u8, KeyCode),
+    Text(&'static str),
+}
+
+impl LeaderAction {
+    async fn run(self) {
+        match self {
+            LeaderAction::Tap(modifiers, key) => tap_key(modifiers, key).await,
+            LeaderAction::Text(text) => type_text(text).await,
+        }
+    }
+}
+
+pub(crate) type LeaderSequence = lily58_core::leader::LeaderSequence<LeaderAction>;
+
+// Keys on the leader layer do nothing, so look up the letter or digit on the base layer
+fn base_layer_key(action: KeyAction) -> Option<u8> {
+    match action {
+        KeyAction::Single(Action::Key(key)) => key_char(key as u16),
+        _ => None,
+    }
+}
+
+/// Collects the keys typed on the leader layer, then leaves it and runs the action of the sequence
+/// they make up. Gives up without doing anything on a key that doesn't continue any sequence, or
+/// once `LEADER_TIMEOUT` passes.
+pub(crate) async fn run_leader(keymap: &LiveKeymap<'_>) -> ! {
+    let mut subscriber = events::subscribe("leader").await;
+    loop {
+        if !matches!(
+            subscriber.next_message_pure().await,
+            ControllerEvent::Layer(layer) if layer as usize == LEADER_LAYER
+        ) {
+            continue;
+        }
+
+        let started = Instant::now();
+        let mut leader = Leader::start(
+            LEADER_SEQUENCES,
+            LEADER_TIMEOUT.as_millis() as u32,
+            started.as_millis() as u32,
+        );
+        let mut left_layer = false;
+        let action = loop {
+            match select(
+                subscriber.next_message_pure(),
+                Timer::at(started + LEADER_TIMEOUT),
+            )
+            .await
+            {
+                Either::First(ControllerEvent::Key(event, action))
+                    if event.pressed && action != LDR =>
+                {
+                    let key = base_layer_key(base_action(keymap, event.pos));
+                    match leader.key(key, Instant::now().as_millis() as u32) {
+                        Step::Run(action) => break Some(action),
+                        Step::Pending => {}
+                        Step::Cancel => break None,
+                    }
+                }
+                // Left with `LDR` on another layer
+                Either::First(ControllerEvent::Layer(layer)) if (layer as usize) < LEADER_LAYER => {
+                    left_layer = true;
+                    break None;
+                }
+                Either::First(_) => {}
+                Either::Second(()) => break None,
+            }
+        };
+
+        if !left_layer {
+            set_layer(keymap, LEADER_LAYER, false);
+        }
+        if let Some(action) = action {
+            action.run().await;
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use embassy_rp::dma::Channel;
+use embassy_rp::peripherals::PIO1;
+use embassy_rp::pio::{self, Pio, PioPin};
+use embassy_rp::pio_programs::ws2812::{PioWs2812, PioWs2812Program};
+use embassy_rp::{bind_interrupts, Peri};
+use embassy_time::{Duration, Instant, Timer};
+use portable_atomic::{AtomicU32, AtomicU8, Ordering};
+use rmk::debounce::{DebounceState, DebouncerTrait};
+use rmk::matrix::KeyState;
+use smart_leds::RGB8;
+
+use crate::keymap::NUM_LAYERS;
+use crate::split_sync::SYNCED_STATE;
+
+// Underglow LEDs on each half
+pub(crate) const NUM_LEDS: usize = 6;
+pub(crate) type Leds = PioWs2812<'static, PIO1, 0, NUM_LEDS>;
+
+const FRAME_TIME: Duration = Duration::from_millis(20);
+pub(crate) const BREATHING_PERIOD_MS: u32 = 4000;
+const REACTIVE_FADE_MS: u32 = 500;
+const LAYER_COLORS: [RGB8; NUM_LAYERS] = [
+    RGB8::new(0, 64, 255),
+    RGB8::new(0, 160, 255),
+    RGB8::new(255, 160, 0),
+    RGB8::new(0, 255, 64),
+    RGB8::new(255, 64, 0),
+    RGB8::new(255, 255, 255),
+    RGB8::new(160, 0, 255),
+];
+
+bind_interrupts!(struct LightingIrqs {
+    PIO1_IRQ_0 => pio::InterruptHandler<PIO1>;
+});
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LightingEffect {
+    Off,
+    /// Solid color of the active layer
+    Layer,
+    /// Flash the layer color on every key press on this half
+    Reactive,
+    /// Slowly pulse the layer color
+    Breathing,
+}
+
+impl LightingEffect {
+    pub(crate) const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(LightingEffect::Off),
+            1 => Some(LightingEffect::Layer),
+            2 => Some(LightingEffect::Reactive),
+            3 => Some(LightingEffect::Breathing),
+            _ => None,
+        }
+    }
+}
+
+static LIGHTING_EFFECT: AtomicU8 = AtomicU8::new(LightingEffect::Layer as u8);
+// Time in milliseconds of the last key press on this half
+static LAST_KEY_PRESS: AtomicU32 = AtomicU32::new(0);
+// Time in milliseconds the breathing effect's current period started. Each half counts it on its
+// own clock, the central restarts it on both at every period so they don't drift apart.
+static BREATHING_START: AtomicU32 = AtomicU32::new(0);
+
+pub(crate) fn lighting_effect() -> LightingEffect {
+    LightingEffect::from_u8(LIGHTING_EFFECT.load(Ordering::Relaxed)).unwrap_or(LightingEffect::Off)
+}
+
+// Both halves set the effect at about the same time, and start breathing from there
+pub(crate) fn set_lighting_effect(effect: LightingEffect) {
+    LIGHTING_EFFECT.store(effect as u8, Ordering::Relaxed);
+    restart_breathing();
+}
+
+pub(crate) fn restart_breathing() {
+    BREATHING_START.store(Instant::now().as_millis() as u32, Ordering::Relaxed);
+}
+
+/// Wraps a debouncer to record key presses for the reactive effect.
+pub(crate) struct KeyActivity<D> {
+    debouncer: D,
+}
+
+impl<D> KeyActivity<D> {
+    pub(crate) fn new(debouncer: D) -> Self {
+        Self { debouncer }
+    }
+}
+
+impl<D, const INPUT_PIN_NUM: usize, const OUTPUT_PIN_NUM: usize>
+    DebouncerTrait<INPUT_PIN_NUM, OUTPUT_PIN_NUM> for KeyActivity<D>
+where
+    D: DebouncerTrait<INPUT_PIN_NUM, OUTPUT_PIN_NUM>,
+{
+    fn detect_change_with_debounce(
+        &mut self,
+        in_idx: usize,
+        out_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        let state = self
+            .debouncer
+            .detect_change_with_debounce(in_idx, out_idx, pin_state, key_state);
+        if pin_state && matches!(state, DebounceState::Debounced) {
+            LAST_KEY_PRESS.store(Instant::now().as_millis() as u32, Ordering::Relaxed);
+        }
+        state
+    }
+}
+
+pub(crate) fn init_lighting(
+    pio: Peri<'static, PIO1>,
+    dma: Peri<'static, impl Channel>,
+    pin: Peri<'static, impl PioPin>,
+) -> Leds {
+    let Pio {
+        mut common, sm0, ..
+    } = Pio::new(pio, LightingIrqs);
+    let program = PioWs2812Program::new(&mut common);
+    PioWs2812::new(&mut common, sm0, dma, pin, &program)
+}
+
+const fn scale(color: RGB8, level: u8) -> RGB8 {
+    RGB8::new(
+        (color.r as u16 * level as u16 / 255) as u8,
+        (color.g as u16 * level as u16 / 255) as u8,
+        (color.b as u16 * level as u16 / 255) as u8,
+    )
+}
+
+/// Renders the current effect in the color of the synced layer, and turns the LEDs off while the
+/// keyboard sleeps.
+pub(crate) async fn run_lighting(mut leds: Leds) -> ! {
+    let mut receiver = SYNCED_STATE.receiver().unwrap();
+    loop {
+        let state = receiver.try_get().unwrap_or_default();
+        let now = Instant::now().as_millis() as u32;
+        let level = match lighting_effect() {
+            _ if state.sleeping => 0,
+            LightingEffect::Off => 0,
+            LightingEffect::Layer => 255,
+            LightingEffect::Reactive => {
+                let elapsed = now.wrapping_sub(LAST_KEY_PRESS.load(Ordering::Relaxed));
+                255 - (elapsed.min(REACTIVE_FADE_MS) * 255 / REACTIVE_FADE_MS) as u8
+            }
+            LightingEffect::Breathing => {
+                let phase =
+                    now.wrapping_sub(BREATHING_START.load(Ordering::Relaxed)) % BREATHING_PERIOD_MS;
+                let half_period = BREATHING_PERIOD_MS / 2;
+                (phase.abs_diff(half_period) * 255 / half_period) as u8
+            }
+        };
+        let color = LAYER_COLORS
+            .get(state.layer as usize)
+            .copied()
+            .unwrap_or_default();
+        leds.write(&[scale(color, level); NUM_LEDS]).await;
+        Timer::after(FRAME_TIME).await;
+    }
+}
@@ -0,0 +1,59 @@
+use core::cell::RefCell;
+
+use rmk::{
+    channel::CONTROLLER_CHANNEL,
+    event::{ControllerEvent, KeyPos, KeyboardEventPos},
+    keymap::KeyMap,
+    types::action::{Action, KeyAction},
+};
+
+use crate::keymap::{BASE_LAYER, COLS, NUM_ENCODERS, NUM_LAYERS, ROWS};
+
+// The keymap as RMK holds it, with the changes made through Vial
+pub(crate) type LiveKeymap<'a> = RefCell<KeyMap<'a, { ROWS * 2 }, COLS, NUM_LAYERS, NUM_ENCODERS>>;
+
+fn key_pos(row: usize, col: usize) -> KeyboardEventPos {
+    KeyboardEventPos::Key(KeyPos {
+        row: row as u8,
+        col: col as u8,
+    })
+}
+
+/// The action at a position of the base layer, as it is now rather than as compiled.
+pub(crate) fn base_action(keymap: &LiveKeymap<'_>, pos: KeyboardEventPos) -> KeyAction {
+    keymap.borrow_mut().get_action_at(pos, BASE_LAYER)
+}
+
+/// Fills an overlay layer from the base layer as it is now, with the action `derive` gives for
+/// each position and base layer action, or transparent where it gives none.
+pub(crate) fn fill_overlay(
+    keymap: &LiveKeymap<'_>,
+    layer: usize,
+    mut derive: impl FnMut(usize, usize, KeyAction) -> Option<KeyAction>,
+) {
+    let mut keymap = keymap.borrow_mut();
+    for row in 0..ROWS * 2 {
+        for col in 0..COLS {
+            let base = keymap.get_action_at(key_pos(row, col), BASE_LAYER);
+            let action = derive(row, col, base).unwrap_or(KeyAction::Single(Action::Transparent));
+            keymap.set_action_at(key_pos(row, col), layer, action);
+        }
+    }
+}
+
+/// Turns a layer on or off and announces the layer on top, as RMK only does that for layer changes
+/// made by its own key actions.
+pub(crate) fn set_layer(keymap: &LiveKeymap<'_>, layer: usize, on: bool) {
+    let top = {
+        let mut keymap = keymap.borrow_mut();
+        if on {
+            keymap.activate_layer(layer as u8);
+        } else {
+            keymap.deactivate_layer(layer as u8);
+        }
+        keymap.get_activated_layer()
+    };
+    CONTROLLER_CHANNEL
+        .immediate_publisher()
+        .publish_immediate(ControllerEvent::Layer(top));
+}
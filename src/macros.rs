@@ -1,8 +1,21 @@
+// Diodes are either `col2row`, reading rows while driving columns, or `row2col`, the other way
+// around. The matrix must be created with the matching `COL2ROW` parameter. RMK scans active high,
+// so inputs have to idle low: `pull` is `Down` (the default), or `None` for boards with external
+// pull-down resistors.
 macro_rules! config_matrix_pins_rp {
+    (peripherals: $p:ident, rows: [$($row_pin:ident), +], cols: [$($col_pin:ident), +], diode: col2row $(, pull: $pull:ident)?) => {
+        config_matrix_pins_rp!(peripherals: $p, input: [$($row_pin), +], output: [$($col_pin), +] $(, pull: $pull)?)
+    };
+    (peripherals: $p:ident, rows: [$($row_pin:ident), +], cols: [$($col_pin:ident), +], diode: row2col $(, pull: $pull:ident)?) => {
+        config_matrix_pins_rp!(peripherals: $p, input: [$($col_pin), +], output: [$($row_pin), +] $(, pull: $pull)?)
+    };
     (peripherals: $p:ident, input: [$($in_pin:ident), *], output: [$($out_pin:ident), +]) => {
+        config_matrix_pins_rp!(peripherals: $p, input: [$($in_pin), *], output: [$($out_pin), +], pull: Down)
+    };
+    (peripherals: $p:ident, input: [$($in_pin:ident), *], output: [$($out_pin:ident), +], pull: $pull:ident) => {
         {
             let mut output_pins = [$(Output::new($p.$out_pin, embassy_rp::gpio::Level::Low)), +];
-            let input_pins = [$(Input::new($p.$in_pin, embassy_rp::gpio::Pull::Down)), +];
+            let input_pins = [$(Input::new($p.$in_pin, embassy_rp::gpio::Pull::$pull)), +];
             output_pins.iter_mut().for_each(|p| {
                 p.set_low();
             });
@@ -10,3 +23,22 @@ macro_rules! config_matrix_pins_rp {
         }
     };
 }
+
+// The split link defaults to single-wire half-duplex, which only needs one TRRS conductor.
+// Boards with a second conductor wired can build with the `full-duplex` feature to get
+// dedicated TX/RX lines. TX on one half must be wired to RX on the other.
+macro_rules! config_split_uart_rp {
+    (
+        peripherals: $p:ident,
+        half_duplex: $pin:ident,
+        full_duplex: { tx: $tx_pin:ident, rx: $rx_pin:ident },
+        rx_buf: $rx_buf:expr,
+        irqs: $irqs:ident
+    ) => {{
+        #[cfg(feature = "full-duplex")]
+        let uart = BufferedUart::new_full_duplex($p.PIO0, $p.$tx_pin, $p.$rx_pin, $rx_buf, $irqs);
+        #[cfg(not(feature = "full-duplex"))]
+        let uart = BufferedUart::new_half_duplex($p.PIO0, $p.$pin, $rx_buf, $irqs);
+        uart
+    }};
+}
@@ -0,0 +1,123 @@
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use lily58_core::mouse::{Acceleration, Curve};
+use rmk::{
+    channel::KEYBOARD_REPORT_CHANNEL, event::ControllerEvent, hid::Report, types::action::KeyAction,
+};
+use usbd_hid::descriptor::MouseReport;
+
+use crate::events;
+use crate::keymap::{MB1, MB2, MB3, MSD, MSL, MSR, MSU, WHD, WHU};
+
+// Time between reports while a movement or wheel key is held
+const MOVE_INTERVAL: Duration = Duration::from_millis(16);
+const WHEEL_INTERVAL: Duration = Duration::from_millis(80);
+
+const CURSOR: Acceleration = Acceleration {
+    min_speed: 1,
+    max_speed: 20,
+    time_to_max_ms: 1500,
+    curve: Curve::Quadratic,
+};
+const WHEEL: Acceleration = Acceleration {
+    min_speed: 1,
+    max_speed: 4,
+    time_to_max_ms: 2000,
+    curve: Curve::Linear,
+};
+
+// The keys are user keycodes handled here rather than RMK's own mouse keycodes. RMK ramps those up
+// on its fixed schedule with no choice of curve, and sends its own mouse reports for them, which
+// would interleave with the ones sent here. With no RMK mouse keycodes in the keymap, this is the
+// only source of mouse reports.
+//
+// Bit of each key in the held set
+const MOUSE_KEYS: [KeyAction; 9] = [MSU, MSD, MSL, MSR, WHU, WHD, MB1, MB2, MB3];
+const MOVE_MASK: u16 = 0b1111;
+const WHEEL_MASK: u16 = 0b11_0000;
+const BUTTON_SHIFT: u16 = 6;
+
+// -1, 0 or 1 depending on which of two opposite keys is held
+fn direction(held: u16, negative: u16, positive: u16) -> i8 {
+    ((held >> positive) & 1) as i8 - ((held >> negative) & 1) as i8
+}
+
+async fn send_report(held: u16, x: i8, y: i8, wheel: i8) {
+    let report = MouseReport {
+        buttons: (held >> BUTTON_SHIFT) as u8,
+        x,
+        y,
+        wheel,
+        pan: 0,
+    };
+    KEYBOARD_REPORT_CHANNEL
+        .send(Report::MouseReport(report))
+        .await;
+}
+
+/// Moves the pointer and scrolls while the mouse keys are held, speeding up along `CURSOR` and
+/// `WHEEL`, and holds the mouse buttons.
+pub(crate) async fn run_mouse_keys() -> ! {
+    let mut subscriber = events::subscribe("mouse keys").await;
+    let mut held: u16 = 0;
+    let mut move_start = Instant::now();
+    let mut wheel_start = Instant::now();
+    let mut next_move = Instant::MAX;
+    let mut next_wheel = Instant::MAX;
+    loop {
+        match select(
+            subscriber.next_message_pure(),
+            Timer::at(next_move.min(next_wheel)),
+        )
+        .await
+        {
+            Either::First(ControllerEvent::Key(event, action)) => {
+                let Some(index) = MOUSE_KEYS.iter().position(|key| *key == action) else {
+                    continue;
+                };
+                let previous = held;
+                if event.pressed {
+                    held |= 1 << index;
+                } else {
+                    held &= !(1 << index);
+                }
+                // Speed builds up from the first of the keys held, and the first step is immediate
+                let now = Instant::now();
+                if previous & MOVE_MASK == 0 && held & MOVE_MASK != 0 {
+                    move_start = now;
+                    next_move = now;
+                }
+                if previous & WHEEL_MASK == 0 && held & WHEEL_MASK != 0 {
+                    wheel_start = now;
+                    next_wheel = now;
+                }
+                if previous >> BUTTON_SHIFT != held >> BUTTON_SHIFT {
+                    send_report(held, 0, 0, 0).await;
+                }
+            }
+            Either::First(_) | Either::Second(()) => {}
+        }
+
+        let now = Instant::now();
+        if now >= next_move || now >= next_wheel {
+            let (mut x, mut y, mut wheel) = (0, 0, 0);
+            if now >= next_move {
+                let speed = CURSOR.speed((now - move_start).as_millis() as u32);
+                x = direction(held, 2, 3) * speed;
+                y = direction(held, 0, 1) * speed;
+                next_move = now + MOVE_INTERVAL;
+            }
+            if now >= next_wheel {
+                wheel = direction(held, 5, 4) * WHEEL.speed((now - wheel_start).as_millis() as u32);
+                next_wheel = now + WHEEL_INTERVAL;
+            }
+            send_report(held, x, y, wheel).await;
+        }
+        if held & MOVE_MASK == 0 {
+            next_move = Instant::MAX;
+        }
+        if held & WHEEL_MASK == 0 {
+            next_wheel = Instant::MAX;
+        }
+    }
+}
@@ -1,9 +1,11 @@
+use core::fmt::Write;
+
+use embassy_futures::select::{select, Either};
 use embassy_rp::{
-    bind_interrupts,
     i2c::{self, Async, I2c, SclPin, SdaPin},
-    peripherals::I2C0,
-    Peripheral,
+    Peri,
 };
+use rmk::heapless::String;
 use ssd1306::{
     mode::{BasicMode, BufferedGraphicsModeAsync, DisplayConfigAsync, TerminalModeAsync},
     prelude::{DisplayRotation, I2CInterface},
@@ -11,35 +13,36 @@ use ssd1306::{
     I2CDisplayInterface, Ssd1306Async,
 };
 
+use crate::board::{OledI2c, OledIrqs};
+use crate::chatter::{ChatterReport, CHATTER_REPORT};
+use crate::keymap::LAYER_NAMES;
+use crate::split_sync::{SyncedState, SYNCED_STATE};
+
 const DISPLAY_SIZE: DisplaySize128x32 = DisplaySize128x32;
-type DisplayInterface = I2CInterface<I2c<'static, I2C0, Async>>;
+type DisplayInterface = I2CInterface<I2c<'static, OledI2c, Async>>;
 pub type Oled<Mode> = Ssd1306Async<DisplayInterface, DisplaySize128x32, Mode>;
 
-bind_interrupts!(struct DisplayIrqs {
-    I2C0_IRQ => i2c::InterruptHandler<I2C0>;
-});
-
 pub fn init_oled(
-    i2c0: I2C0,
-    sda: impl Peripheral<P = impl SdaPin<I2C0>> + 'static,
-    scl: impl Peripheral<P = impl SclPin<I2C0>> + 'static,
+    i2c: Peri<'static, OledI2c>,
+    sda: Peri<'static, impl SdaPin<OledI2c>>,
+    scl: Peri<'static, impl SclPin<OledI2c>>,
     rotation: DisplayRotation,
 ) -> Oled<BasicMode> {
-    let mut i2c0_cfg = i2c::Config::default();
-    i2c0_cfg.frequency = 400_000; // 400 kHz = fast mode
-    let i2c = I2c::new_async(i2c0, scl, sda, DisplayIrqs, i2c0_cfg);
+    let mut config = i2c::Config::default();
+    config.frequency = 400_000; // 400 kHz = fast mode
+    let i2c = I2c::new_async(i2c, scl, sda, OledIrqs, config);
     let interface = I2CDisplayInterface::new(i2c);
     Ssd1306Async::new(interface, DISPLAY_SIZE, rotation)
 }
 
 #[allow(dead_code)]
 pub async fn init_oled_terminal(
-    i2c0: I2C0,
-    sda: impl Peripheral<P = impl SdaPin<I2C0>> + 'static,
-    scl: impl Peripheral<P = impl SclPin<I2C0>> + 'static,
+    i2c: Peri<'static, OledI2c>,
+    sda: Peri<'static, impl SdaPin<OledI2c>>,
+    scl: Peri<'static, impl SclPin<OledI2c>>,
     rotation: DisplayRotation,
 ) -> Oled<TerminalModeAsync> {
-    let mut display = init_oled(i2c0, sda, scl, rotation).into_terminal_mode();
+    let mut display = init_oled(i2c, sda, scl, rotation).into_terminal_mode();
     display.init().await.unwrap();
     display.clear().await.unwrap();
     display
@@ -47,14 +50,48 @@ pub async fn init_oled_terminal(
 
 #[allow(dead_code)]
 pub async fn init_oled_graphics(
-    i2c0: I2C0,
-    sda: impl Peripheral<P = impl SdaPin<I2C0>> + 'static,
-    scl: impl Peripheral<P = impl SclPin<I2C0>> + 'static,
+    i2c: Peri<'static, OledI2c>,
+    sda: Peri<'static, impl SdaPin<OledI2c>>,
+    scl: Peri<'static, impl SclPin<OledI2c>>,
     rotation: DisplayRotation,
 ) -> Oled<BufferedGraphicsModeAsync<DisplaySize128x32>> {
-    let mut display = init_oled(i2c0, sda, scl, rotation).into_buffered_graphics_mode();
+    let mut display = init_oled(i2c, sda, scl, rotation).into_buffered_graphics_mode();
     display.init().await.unwrap();
     display.clear_buffer();
     display.flush().await.unwrap();
     display
 }
+
+/// Shows the synced layer and caps lock state along with the most chattery key on this half, and
+/// turns the display off while the keyboard sleeps.
+pub async fn run_status_display(mut display: Oled<TerminalModeAsync>) -> ! {
+    let mut state_receiver = SYNCED_STATE.receiver().unwrap();
+    let mut chatter_receiver = CHATTER_REPORT.receiver().unwrap();
+    let mut state = SyncedState::default();
+    let mut chatter = None;
+    loop {
+        match select(state_receiver.changed(), chatter_receiver.changed()).await {
+            Either::First(new_state) => state = new_state,
+            Either::Second(report) => chatter = Some(report),
+        }
+        let _ = display.set_display_on(!state.sleeping).await;
+        if state.sleeping {
+            continue;
+        }
+
+        let mut text: String<64> = String::new();
+        let _ = write!(
+            text,
+            "{}",
+            LAYER_NAMES.get(state.layer as usize).unwrap_or(&"?")
+        );
+        if state.led_indicator.caps_lock() {
+            let _ = write!(text, " CAPS");
+        }
+        if let Some(ChatterReport { row, col, count }) = chatter {
+            let _ = write!(text, "\nChatter r{}c{} x{}", row, col, count);
+        }
+        let _ = display.clear().await;
+        let _ = display.write_str(&text).await;
+    }
+}
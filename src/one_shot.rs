@@ -0,0 +1,50 @@
+use embassy_time::{Duration, Instant};
+use lily58_core::link::LinkCommand;
+use rmk::{
+    event::{ControllerEvent, KeyboardEventPos},
+    types::action::KeyAction,
+};
+
+use crate::central_sync::send_link_command;
+use crate::events;
+use crate::key_lock::lock_last_pressed;
+use crate::keymap::{OAL, OCT, OGU, OLW, ORA, OSH, ROWS};
+
+// Second tap has to come this soon after the first to lock
+const LOCK_TAP_TERM: Duration = Duration::from_millis(300);
+const ONE_SHOT_KEYS: [KeyAction; 6] = [OSH, OCT, OAL, OGU, OLW, ORA];
+
+/// Locks a one-shot key that's tapped twice by holding it down until it's pressed again, which
+/// releases it along with the physical key.
+pub(crate) async fn run_one_shot_lock() -> ! {
+    let mut subscriber = events::subscribe("one-shot lock").await;
+    let mut last_tap: Option<((u8, u8), Instant)> = None;
+    loop {
+        let ControllerEvent::Key(event, action) = subscriber.next_message_pure().await else {
+            continue;
+        };
+        let KeyboardEventPos::Key(pos) = event.pos else {
+            continue;
+        };
+        if !event.pressed {
+            continue;
+        }
+        if !ONE_SHOT_KEYS.contains(&action) {
+            last_tap = None;
+            continue;
+        }
+
+        let key = (pos.row, pos.col);
+        if matches!(last_tap, Some((tapped, at)) if tapped == key && at.elapsed() < LOCK_TAP_TERM) {
+            last_tap = None;
+            // The second tap is the key last pressed on its half, and still down
+            if (pos.row as usize) < ROWS {
+                lock_last_pressed();
+            } else {
+                send_link_command(LinkCommand::LockKey).await;
+            }
+        } else {
+            last_tap = Some((key, Instant::now()));
+        }
+    }
+}
@@ -5,25 +5,53 @@
 mod keymap;
 #[macro_use]
 mod macros;
+#[macro_use]
+mod board;
+mod boot;
+mod chatter;
+mod debounce;
+mod events;
+mod flash_config;
+mod key_lock;
+#[cfg(feature = "rgb")]
+mod lighting;
+#[cfg(feature = "display")]
+mod oled;
+mod peripheral_sync;
+mod split_sync;
 
 use embassy_executor::Spawner;
-use embassy_rp::gpio::{Input, Output, Pin, Pull};
+use embassy_rp::bind_interrupts;
+use embassy_rp::flash::{self, Flash};
+use embassy_rp::gpio::{Input, Output};
 use embassy_rp::peripherals::{PIO0, USB};
 use embassy_rp::usb::InterruptHandler;
-use embassy_rp::{bind_interrupts, Peri};
-use embassy_time::Duration;
 use panic_probe as _;
 use rmk::channel::EVENT_CHANNEL;
-use rmk::debounce::default_debouncer::DefaultDebouncer;
-use rmk::futures::future::join;
+use rmk::futures::future::join5;
+use rmk::input_device::rotary_encoder::RotaryEncoder;
 use rmk::matrix::Matrix;
 use rmk::run_devices;
 use rmk::split::peripheral::run_rmk_split_peripheral;
 use rmk::split::rp::uart::{BufferedUart, UartInterruptHandler};
 use rmk::split::SPLIT_MESSAGE_MAX_SIZE;
+#[cfg(feature = "display")]
+use ssd1306::{mode::TerminalModeAsync, prelude::DisplayRotation};
 use static_cell::StaticCell;
 
-use crate::keymap::{COLS, ROWS};
+use crate::board::{COL2ROW, INPUT_PIN_NUM, OUTPUT_PIN_NUM};
+use crate::boot::check_boot_magic;
+use crate::chatter::ChatterDetector;
+use crate::debounce::{set_debounce_config, Debouncer};
+use crate::events::run_event_forwarder;
+use crate::flash_config::{DEFAULT_CONFIG, FLASH_SIZE};
+use crate::key_lock::KeyLock;
+#[cfg(feature = "rgb")]
+use crate::lighting::{init_lighting, run_lighting, KeyActivity, Leds};
+#[cfg(feature = "display")]
+use crate::oled::{init_oled_terminal, run_status_display, Oled};
+use crate::peripheral_sync::run_peripheral_sync;
+use crate::split_sync::run_state_sync;
 
 bind_interrupts!(struct Irqs {
     USBCTRL_IRQ => InterruptHandler<USB>;
@@ -31,49 +59,82 @@ bind_interrupts!(struct Irqs {
 });
 
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
+#[cfg_attr(
+    not(any(feature = "display", feature = "rgb")),
+    allow(unused_variables)
+)]
+async fn main(spawner: Spawner) {
     // Initialize peripherals
     let p = embassy_rp::init(Default::default());
 
-    unsafe {
-        let row4_pin = p.PIN_9.clone_unchecked();
-        let col5_pin = p.PIN_21.clone_unchecked();
-        if is_key_pressed(row4_pin, col5_pin) {
-            embassy_rp::rom_data::reset_to_usb_boot(0, 0);
-        }
-    }
-
-    let (row_pins, col_pins) = config_matrix_pins_rp!(
-        peripherals: p,
-        input: [PIN_5, PIN_6, PIN_7, PIN_8, PIN_9],
-        output: [PIN_27, PIN_26, PIN_22, PIN_20, PIN_23, PIN_21]
-    );
+    let (input_pins, mut output_pins) = board_matrix_pins!(p);
+    let boot_magic = check_boot_magic(&input_pins, &mut output_pins);
 
     static RX_BUF: StaticCell<[u8; SPLIT_MESSAGE_MAX_SIZE]> = StaticCell::new();
     let rx_buf = &mut RX_BUF.init([0; SPLIT_MESSAGE_MAX_SIZE])[..];
-    let uart_instance = BufferedUart::new_half_duplex(p.PIO0, p.PIN_1, rx_buf, Irqs);
+    let uart_instance = board_split_uart!(peripheral, p, rx_buf, Irqs);
+
+    // Use the debounce settings last pushed by the central until it connects
+    let mut flash = Flash::<_, flash::Blocking, FLASH_SIZE>::new_blocking(p.FLASH);
+    if boot_magic.clear_storage {
+        flash_config::store(&mut flash, DEFAULT_CONFIG);
+    }
+    if !boot_magic.safe_mode {
+        set_debounce_config(flash_config::load(&mut flash).debounce);
+    }
 
     // Define the matrix
-    let debouncer = DefaultDebouncer::<ROWS, COLS>::new();
-    let mut matrix = Matrix::<_, _, _, ROWS, COLS, true>::new(row_pins, col_pins, debouncer);
+    let debouncer = ChatterDetector::new(Debouncer::<INPUT_PIN_NUM, OUTPUT_PIN_NUM>::new());
+    #[cfg(feature = "rgb")]
+    let debouncer = KeyActivity::new(debouncer);
+    let debouncer = KeyLock::new(debouncer);
+    let mut matrix = Matrix::<_, _, _, INPUT_PIN_NUM, OUTPUT_PIN_NUM, COL2ROW>::new(
+        input_pins,
+        output_pins,
+        debouncer,
+    );
+
+    // The central's encoder comes first in the encoder map
+    let (encoder_pin_a, encoder_pin_b) = board_encoder_pins!(p);
+    let mut encoder = RotaryEncoder::with_resolution(encoder_pin_a, encoder_pin_b, 4, false, 1);
 
     // Initialize the OLED display
-    // let mut display =
-    //     init_oled_terminal(p.I2C0, p.PIN_16, p.PIN_17, DisplayRotation::Rotate180).await;
-    // let _ = display.write_str("Lily58").await;
+    #[cfg(feature = "display")]
+    {
+        let (i2c, sda, scl) = board_oled_pins!(p);
+        let display = init_oled_terminal(i2c, sda, scl, DisplayRotation::Rotate180).await;
+        spawner.spawn(display_task(display)).unwrap();
+    }
 
-    join(
-        run_devices!((matrix) => EVENT_CHANNEL),
-        run_rmk_split_peripheral(uart_instance),
+    // Initialize the underglow
+    #[cfg(feature = "rgb")]
+    {
+        let leds = init_lighting(p.PIO1, p.DMA_CH0, board_rgb_pin!(p));
+        spawner.spawn(lighting_task(leds)).unwrap();
+    }
+
+    join5(
+        run_devices!((matrix, encoder) => EVENT_CHANNEL),
+        async {
+            if !boot_magic.disable_split {
+                run_rmk_split_peripheral(uart_instance).await
+            }
+        },
+        run_event_forwarder(),
+        run_state_sync(),
+        run_peripheral_sync(flash),
     )
     .await;
 }
 
-fn is_key_pressed<R: Pin, C: Pin>(row_pin: Peri<R>, col_pin: Peri<C>) -> bool {
-    let mut row = Output::new(row_pin, embassy_rp::gpio::Level::Low);
-    let col = Input::new(col_pin, Pull::Up);
+#[cfg(feature = "display")]
+#[embassy_executor::task]
+async fn display_task(display: Oled<TerminalModeAsync>) {
+    run_status_display(display).await
+}
 
-    row.set_low();
-    embassy_time::block_for(Duration::from_millis(1));
-    !col.is_high()
+#[cfg(feature = "rgb")]
+#[embassy_executor::task]
+async fn lighting_task(leds: Leds) {
+    run_lighting(leds).await;
 }
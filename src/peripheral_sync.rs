@@ -0,0 +1,43 @@
+use embassy_rp::flash::{Flash, Mode};
+use embassy_rp::peripherals::FLASH;
+use lily58_core::link::LinkCommand;
+use rmk::event::ControllerEvent;
+
+use crate::debounce::{debounce_config, set_debounce_config};
+use crate::events;
+use crate::flash_config::{self, FLASH_SIZE};
+use crate::key_lock::lock_last_pressed;
+#[cfg(feature = "rgb")]
+use crate::lighting::{restart_breathing, set_lighting_effect, LightingEffect};
+
+/// Carries out the link commands sent by the central, other than those `run_state_sync` tracks:
+/// reboots into the USB bootloader, locks the key last pressed on this half, applies the underglow
+/// effect and keeps its breathing in step, and applies the debounce settings, persisting them.
+pub(crate) async fn run_peripheral_sync<M: Mode>(mut flash: Flash<'_, FLASH, M, FLASH_SIZE>) -> ! {
+    let mut subscriber = events::subscribe("peripheral sync").await;
+    loop {
+        let ControllerEvent::Layer(layer) = subscriber.next_message_pure().await else {
+            continue;
+        };
+        let mut config = debounce_config();
+        match LinkCommand::decode(layer) {
+            Some(LinkCommand::Bootloader) => embassy_rp::rom_data::reset_to_usb_boot(0, 0),
+            Some(LinkCommand::LockKey) => lock_last_pressed(),
+            #[cfg(feature = "rgb")]
+            Some(LinkCommand::SetLightingEffect(effect)) => {
+                if let Some(effect) = LightingEffect::from_u8(effect) {
+                    set_lighting_effect(effect);
+                }
+            }
+            #[cfg(feature = "rgb")]
+            Some(LinkCommand::RestartBreathing) => restart_breathing(),
+            Some(LinkCommand::SetDebounceAlgorithm(algorithm)) => config.algorithm = algorithm,
+            Some(LinkCommand::SetDebounceTime(time_ms)) => config.time_ms = time_ms,
+            _ => {}
+        }
+        if config != debounce_config() {
+            set_debounce_config(config);
+            flash_config::update(&mut flash, |board_config| board_config.debounce = config);
+        }
+    }
+}
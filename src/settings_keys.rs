@@ -0,0 +1,74 @@
+use lily58_core::debounce::{DebounceConfig, MAX_DEBOUNCE_MS};
+use lily58_core::link::LinkCommand;
+use rmk::event::ControllerEvent;
+
+use crate::central_sync::send_link_command;
+use crate::debounce::{debounce_config, set_debounce_config};
+use crate::events;
+use crate::flash_config;
+#[cfg(feature = "rgb")]
+use crate::keymap::LMD;
+use crate::keymap::{DBA, DBD, DBU};
+#[cfg(feature = "rgb")]
+use crate::lighting::{lighting_effect, set_lighting_effect, LightingEffect};
+use crate::storage::SharedFlash;
+
+#[cfg(feature = "rgb")]
+const fn next_effect(effect: LightingEffect) -> LightingEffect {
+    match effect {
+        LightingEffect::Off => LightingEffect::Layer,
+        LightingEffect::Layer => LightingEffect::Reactive,
+        LightingEffect::Reactive => LightingEffect::Breathing,
+        LightingEffect::Breathing => LightingEffect::Off,
+    }
+}
+
+async fn push_debounce(config: DebounceConfig) {
+    send_link_command(LinkCommand::SetDebounceAlgorithm(config.algorithm)).await;
+    send_link_command(LinkCommand::SetDebounceTime(config.time_ms)).await;
+}
+
+#[cfg(feature = "rgb")]
+async fn push_lighting() {
+    send_link_command(LinkCommand::SetLightingEffect(lighting_effect() as u8)).await;
+}
+
+/// Handles the keys on the lower layer that change settings of both halves: `DBD`/`DBU` adjust the
+/// debounce time and `DBA` cycles the algorithm, which are persisted, and `LMD` cycles the
+/// underglow effect. Each is pushed to the peripheral whenever it changes or the peripheral
+/// connects.
+pub(crate) async fn run_settings_keys(flash: &SharedFlash) -> ! {
+    let mut subscriber = events::subscribe("settings keys").await;
+    loop {
+        let mut config = debounce_config();
+        match subscriber.next_message_pure().await {
+            ControllerEvent::Key(event, action) if event.pressed && action == DBD => {
+                config.time_ms = config.time_ms.saturating_sub(1).max(1);
+            }
+            ControllerEvent::Key(event, action) if event.pressed && action == DBU => {
+                config.time_ms = (config.time_ms + 1).min(MAX_DEBOUNCE_MS);
+            }
+            ControllerEvent::Key(event, action) if event.pressed && action == DBA => {
+                config.algorithm = config.algorithm.next();
+            }
+            #[cfg(feature = "rgb")]
+            ControllerEvent::Key(event, action) if event.pressed && action == LMD => {
+                set_lighting_effect(next_effect(lighting_effect()));
+                push_lighting().await;
+                continue;
+            }
+            ControllerEvent::SplitPeripheral(_, true) => {
+                push_debounce(config).await;
+                #[cfg(feature = "rgb")]
+                push_lighting().await;
+                continue;
+            }
+            _ => continue,
+        }
+        set_debounce_config(config);
+        flash_config::update(&mut *flash.lock().await, |board_config| {
+            board_config.debounce = config
+        });
+        push_debounce(config).await;
+    }
+}
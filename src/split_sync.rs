@@ -0,0 +1,53 @@
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, watch::Watch};
+use lily58_core::link::{LinkCommand, LINK_COMMAND_BASE};
+use rmk::{event::ControllerEvent, types::led_indicator::LedIndicator};
+
+use crate::events;
+use crate::keymap::NUM_LAYERS;
+
+// Link commands reach the peripheral as layer changes past the end of the keymap, see
+// `lily58_core::link`. That works because RMK only ever writes the layer of a
+// `ControllerEvent::Layer`, it never reads it back to index the keymap. The central's split driver
+// sends the `u8` to the peripheral as it is, and the peripheral publishes it on its own
+// `CONTROLLER_CHANNEL`, where there is no keymap at all. Numbers from `LINK_COMMAND_BASE` on only
+// ever reach the tasks in this firmware, through `events`, which all decode them first. Anything in
+// RMK that starts reading these events has to be checked when updating it.
+const _: () = assert!(NUM_LAYERS <= LINK_COMMAND_BASE as usize);
+
+/// Keyboard state mirrored from the central.
+#[cfg_attr(not(feature = "display"), allow(dead_code))] // only the OLED shows all of it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct SyncedState {
+    pub layer: u8,
+    pub led_indicator: LedIndicator,
+    pub sleeping: bool,
+}
+
+pub(crate) static SYNCED_STATE: Watch<CriticalSectionRawMutex, SyncedState, 2> = Watch::new();
+
+/// Tracks the state broadcast by the central and publishes it to `SYNCED_STATE`. Runs on both
+/// halves, the central sees its own layer changes, lock LEDs and link commands.
+pub(crate) async fn run_state_sync() -> ! {
+    let mut subscriber = events::subscribe("state sync").await;
+    let sender = SYNCED_STATE.sender();
+    let mut state = SyncedState::default();
+    sender.send(state);
+    loop {
+        match subscriber.next_message_pure().await {
+            ControllerEvent::Layer(layer) if (layer as usize) < NUM_LAYERS => state.layer = layer,
+            ControllerEvent::Layer(layer) => match LinkCommand::decode(layer) {
+                Some(LinkCommand::Sleep) => state.sleeping = true,
+                Some(LinkCommand::Wake) => state.sleeping = false,
+                Some(LinkCommand::SetLedIndicator(bits)) => {
+                    state.led_indicator = LedIndicator::from_bits(bits)
+                }
+                _ => continue,
+            },
+            ControllerEvent::KeyboardIndicator(led_indicator) => {
+                state.led_indicator = led_indicator
+            }
+            _ => continue,
+        }
+        sender.send(state);
+    }
+}
@@ -0,0 +1,50 @@
+use embassy_rp::flash::{self, Async, Flash, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embedded_storage_async::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+
+use crate::flash_config::{FLASH_SIZE, STORAGE_OFFSET};
+
+// RMK's storage follows the board settings sector, see `flash_config`, and dynamic macros have the
+// sector just below the storage region
+pub(crate) const RMK_STORAGE_OFFSET: usize = STORAGE_OFFSET + ERASE_SIZE;
+pub(crate) const RMK_STORAGE_SECTORS: u8 = 2;
+pub(crate) const MACRO_OFFSET: u32 = (STORAGE_OFFSET - ERASE_SIZE) as u32;
+
+type AsyncFlash = Flash<'static, FLASH, Async, FLASH_SIZE>;
+
+/// The flash on the central, shared by RMK's storage and the settings and macros kept next to it.
+/// They take turns, so a blocking erase or write never runs in the middle of one of RMK's DMA reads.
+pub(crate) type SharedFlash = Mutex<CriticalSectionRawMutex, AsyncFlash>;
+
+/// Hands the shared flash to RMK's storage, locking it for each operation.
+pub(crate) struct RmkFlash<'a>(pub &'a SharedFlash);
+
+impl ErrorType for RmkFlash<'_> {
+    type Error = flash::Error;
+}
+
+impl ReadNorFlash for RmkFlash<'_> {
+    const READ_SIZE: usize = <AsyncFlash as ReadNorFlash>::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        ReadNorFlash::read(&mut *self.0.lock().await, offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        FLASH_SIZE
+    }
+}
+
+impl NorFlash for RmkFlash<'_> {
+    const WRITE_SIZE: usize = <AsyncFlash as NorFlash>::WRITE_SIZE;
+    const ERASE_SIZE: usize = <AsyncFlash as NorFlash>::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        NorFlash::erase(&mut *self.0.lock().await, from, to).await
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        NorFlash::write(&mut *self.0.lock().await, offset, bytes).await
+    }
+}
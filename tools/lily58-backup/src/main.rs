@@ -0,0 +1,559 @@
+//! Backs up and restores the keymap, encoders, macros, combos, tap dances, forks and behavior
+//! settings saved on a Lily58 running this firmware.
+//! Talks to the keyboard over the VIA/Vial raw HID protocol that RMK already implements.
+
+use std::{env, fs, process::ExitCode};
+
+use hidapi::{HidApi, HidDevice};
+
+const VID: u16 = 0x4c4b;
+const PID: u16 = 0x4643;
+const RAW_HID_USAGE_PAGE: u16 = 0xFF60;
+const RAW_HID_USAGE: u16 = 0x61;
+const REPORT_SIZE: usize = 32;
+const TIMEOUT_MS: i32 = 1000;
+
+// Matrix size as seen over VIA, see `vial.json`
+const ROWS: u8 = 10;
+const COLS: u8 = 6;
+const ENCODERS: u8 = 2;
+
+const BACKUP_MAGIC: &[u8; 4] = b"L58B";
+const BACKUP_VERSION: u8 = 1;
+// Combos, tap dances and key overrides all take 10 bytes. Combos are four trigger keys and the
+// output key, tap dances the tap, hold, double tap and tap-hold keys and the tapping term. Key
+// overrides are how Vial edits RMK's forks.
+const ENTRY_SIZE: usize = 10;
+// Counter-clockwise then clockwise, as big endian keycodes
+const ENCODER_SIZE: usize = 4;
+// Values of the behavior settings are at most 4 bytes, shorter ones are zero padded
+const SETTING_SIZE: usize = 4;
+
+const ID_DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE: u8 = 0x0D;
+const ID_DYNAMIC_KEYMAP_MACRO_GET_BUFFER: u8 = 0x0E;
+const ID_DYNAMIC_KEYMAP_MACRO_SET_BUFFER: u8 = 0x0F;
+const ID_DYNAMIC_KEYMAP_GET_LAYER_COUNT: u8 = 0x11;
+const ID_DYNAMIC_KEYMAP_GET_BUFFER: u8 = 0x12;
+const ID_DYNAMIC_KEYMAP_SET_BUFFER: u8 = 0x13;
+const ID_VIAL_PREFIX: u8 = 0xFE;
+const VIAL_GET_ENCODER: u8 = 0x03;
+const VIAL_SET_ENCODER: u8 = 0x04;
+const VIAL_QMK_SETTINGS_QUERY: u8 = 0x09;
+const VIAL_QMK_SETTINGS_GET: u8 = 0x0A;
+const VIAL_QMK_SETTINGS_SET: u8 = 0x0B;
+const VIAL_DYNAMIC_ENTRY_OP: u8 = 0x0D;
+const DYNAMIC_VIAL_GET_NUMBER_OF_ENTRIES: u8 = 0x00;
+const DYNAMIC_VIAL_TAP_DANCE_GET: u8 = 0x01;
+const DYNAMIC_VIAL_TAP_DANCE_SET: u8 = 0x02;
+const DYNAMIC_VIAL_COMBO_GET: u8 = 0x03;
+const DYNAMIC_VIAL_COMBO_SET: u8 = 0x04;
+const DYNAMIC_VIAL_KEY_OVERRIDE_GET: u8 = 0x05;
+const DYNAMIC_VIAL_KEY_OVERRIDE_SET: u8 = 0x06;
+// Ends the list of settings ids returned by a query
+const QMK_SETTINGS_END: u16 = 0xFFFF;
+
+// Buffer reads and writes carry a 4 byte header: command id, big endian offset and size
+const BUFFER_CHUNK_SIZE: usize = REPORT_SIZE - 4;
+
+struct Keyboard {
+    device: HidDevice,
+}
+
+impl Keyboard {
+    fn open() -> Result<Self, String> {
+        let api = HidApi::new().map_err(|e| format!("Cannot access HID devices: {e}"))?;
+        let info = api
+            .device_list()
+            .find(|d| {
+                d.vendor_id() == VID
+                    && d.product_id() == PID
+                    && d.usage_page() == RAW_HID_USAGE_PAGE
+                    && d.usage() == RAW_HID_USAGE
+            })
+            .ok_or("Lily58 not found, is it plugged in?")?;
+        let device = info
+            .open_device(&api)
+            .map_err(|e| format!("Cannot open the Lily58: {e}"))?;
+        Ok(Self { device })
+    }
+
+    fn command(&self, request: &[u8]) -> Result<[u8; REPORT_SIZE], String> {
+        // hidapi expects the report id first, raw HID doesn't use one
+        let mut report = [0; REPORT_SIZE + 1];
+        report[1..=request.len()].copy_from_slice(request);
+        self.device
+            .write(&report)
+            .map_err(|e| format!("Cannot send to the Lily58: {e}"))?;
+
+        let mut response = [0; REPORT_SIZE];
+        let read = self
+            .device
+            .read_timeout(&mut response, TIMEOUT_MS)
+            .map_err(|e| format!("Cannot read from the Lily58: {e}"))?;
+        if read == 0 {
+            return Err("Timed out waiting for the Lily58".into());
+        }
+        Ok(response)
+    }
+
+    fn read_buffer(&self, command_id: u8, len: usize) -> Result<Vec<u8>, String> {
+        let mut buffer = Vec::with_capacity(len);
+        while buffer.len() < len {
+            let size = BUFFER_CHUNK_SIZE.min(len - buffer.len());
+            let [offset_hi, offset_lo] = (buffer.len() as u16).to_be_bytes();
+            let response = self.command(&[command_id, offset_hi, offset_lo, size as u8])?;
+            buffer.extend_from_slice(&response[4..4 + size]);
+        }
+        Ok(buffer)
+    }
+
+    fn write_buffer(&self, command_id: u8, data: &[u8]) -> Result<(), String> {
+        for (i, chunk) in data.chunks(BUFFER_CHUNK_SIZE).enumerate() {
+            let [offset_hi, offset_lo] = ((i * BUFFER_CHUNK_SIZE) as u16).to_be_bytes();
+            let mut request = vec![command_id, offset_hi, offset_lo, chunk.len() as u8];
+            request.extend_from_slice(chunk);
+            self.command(&request)?;
+        }
+        Ok(())
+    }
+
+    fn layer_count(&self) -> Result<u8, String> {
+        Ok(self.command(&[ID_DYNAMIC_KEYMAP_GET_LAYER_COUNT])?[1])
+    }
+
+    fn macro_buffer_size(&self) -> Result<usize, String> {
+        let response = self.command(&[ID_DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE])?;
+        Ok(u16::from_be_bytes([response[1], response[2]]) as usize)
+    }
+
+    /// Room for tap dances, combos and key overrides, in that order.
+    fn entry_counts(&self) -> Result<[u8; 3], String> {
+        let response = self.command(&[
+            ID_VIAL_PREFIX,
+            VIAL_DYNAMIC_ENTRY_OP,
+            DYNAMIC_VIAL_GET_NUMBER_OF_ENTRIES,
+        ])?;
+        Ok(response[..3].try_into().unwrap())
+    }
+
+    fn read_entries(&self, op: u8, count: u8) -> Result<Vec<[u8; ENTRY_SIZE]>, String> {
+        (0..count)
+            .map(|i| {
+                let response = self.command(&[ID_VIAL_PREFIX, VIAL_DYNAMIC_ENTRY_OP, op, i])?;
+                Ok(response[1..1 + ENTRY_SIZE].try_into().unwrap())
+            })
+            .collect()
+    }
+
+    fn write_entries(&self, op: u8, entries: &[[u8; ENTRY_SIZE]]) -> Result<(), String> {
+        for (i, entry) in entries.iter().enumerate() {
+            let mut request = vec![ID_VIAL_PREFIX, VIAL_DYNAMIC_ENTRY_OP, op, i as u8];
+            request.extend_from_slice(entry);
+            self.command(&request)?;
+        }
+        Ok(())
+    }
+
+    fn read_encoders(&self, layers: u8) -> Result<Vec<[u8; ENCODER_SIZE]>, String> {
+        let mut encoders = Vec::new();
+        for layer in 0..layers {
+            for encoder in 0..ENCODERS {
+                let response = self.command(&[ID_VIAL_PREFIX, VIAL_GET_ENCODER, layer, encoder])?;
+                encoders.push(response[..ENCODER_SIZE].try_into().unwrap());
+            }
+        }
+        Ok(encoders)
+    }
+
+    fn write_encoders(&self, encoders: &[[u8; ENCODER_SIZE]]) -> Result<(), String> {
+        for (i, actions) in encoders.iter().enumerate() {
+            let (layer, encoder) = ((i / ENCODERS as usize) as u8, (i % ENCODERS as usize) as u8);
+            for (clockwise, keycode) in actions.as_chunks::<2>().0.iter().enumerate() {
+                self.command(&[
+                    ID_VIAL_PREFIX,
+                    VIAL_SET_ENCODER,
+                    layer,
+                    encoder,
+                    clockwise as u8,
+                    keycode[0],
+                    keycode[1],
+                ])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn setting_ids(&self) -> Result<Vec<u16>, String> {
+        // Each query returns the ids greater than the one asked for, until the end marker. Stops
+        // early on anything else, so a keyboard answering with garbage can't keep it looping.
+        let mut ids: Vec<u16> = Vec::new();
+        loop {
+            let after = ids.last().copied().unwrap_or(0);
+            let [after_lo, after_hi] = after.to_le_bytes();
+            let response =
+                self.command(&[ID_VIAL_PREFIX, VIAL_QMK_SETTINGS_QUERY, after_lo, after_hi])?;
+            for id in response.as_chunks::<2>().0 {
+                let id = u16::from_le_bytes(*id);
+                if id == QMK_SETTINGS_END || id <= ids.last().copied().unwrap_or(after) {
+                    return Ok(ids);
+                }
+                ids.push(id);
+            }
+        }
+    }
+
+    fn read_settings(&self) -> Result<Vec<(u16, [u8; SETTING_SIZE])>, String> {
+        self.setting_ids()?
+            .into_iter()
+            .map(|id| {
+                let [id_lo, id_hi] = id.to_le_bytes();
+                let response =
+                    self.command(&[ID_VIAL_PREFIX, VIAL_QMK_SETTINGS_GET, id_lo, id_hi])?;
+                if response[0] != 0 {
+                    return Err(format!("Cannot read setting {id}"));
+                }
+                Ok((id, response[1..1 + SETTING_SIZE].try_into().unwrap()))
+            })
+            .collect()
+    }
+
+    fn write_settings(&self, settings: &[(u16, [u8; SETTING_SIZE])]) -> Result<(), String> {
+        for (id, value) in settings {
+            let [id_lo, id_hi] = id.to_le_bytes();
+            let mut request = vec![ID_VIAL_PREFIX, VIAL_QMK_SETTINGS_SET, id_lo, id_hi];
+            request.extend_from_slice(value);
+            self.command(&request)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_room(what: &str, len: usize, room: u8) -> Result<(), String> {
+    if len > room as usize {
+        return Err(format!(
+            "Backup has {len} {what}, but the keyboard only has room for {room}"
+        ));
+    }
+    Ok(())
+}
+
+/// Everything saved on the keyboard that can be edited from Vial.
+#[derive(Debug, PartialEq)]
+struct Backup {
+    layers: u8,
+    rows: u8,
+    cols: u8,
+    keymap: Vec<u8>,
+    /// Per layer, then per encoder
+    encoders: Vec<[u8; ENCODER_SIZE]>,
+    macros: Vec<u8>,
+    combos: Vec<[u8; ENTRY_SIZE]>,
+    tap_dances: Vec<[u8; ENTRY_SIZE]>,
+    forks: Vec<[u8; ENTRY_SIZE]>,
+    /// Behavior settings by their Vial id
+    settings: Vec<(u16, [u8; SETTING_SIZE])>,
+}
+
+impl Backup {
+    fn download(keyboard: &Keyboard) -> Result<Self, String> {
+        let layers = keyboard.layer_count()?;
+        let keymap_len = layers as usize * ROWS as usize * COLS as usize * 2;
+        let keymap = keyboard.read_buffer(ID_DYNAMIC_KEYMAP_GET_BUFFER, keymap_len)?;
+        let macros = keyboard.read_buffer(
+            ID_DYNAMIC_KEYMAP_MACRO_GET_BUFFER,
+            keyboard.macro_buffer_size()?,
+        )?;
+        let [tap_dance_count, combo_count, fork_count] = keyboard.entry_counts()?;
+        Ok(Self {
+            layers,
+            rows: ROWS,
+            cols: COLS,
+            keymap,
+            encoders: keyboard.read_encoders(layers)?,
+            macros,
+            combos: keyboard.read_entries(DYNAMIC_VIAL_COMBO_GET, combo_count)?,
+            tap_dances: keyboard.read_entries(DYNAMIC_VIAL_TAP_DANCE_GET, tap_dance_count)?,
+            forks: keyboard.read_entries(DYNAMIC_VIAL_KEY_OVERRIDE_GET, fork_count)?,
+            settings: keyboard.read_settings()?,
+        })
+    }
+
+    fn upload(&self, keyboard: &Keyboard) -> Result<(), String> {
+        let layers = keyboard.layer_count()?;
+        if (self.layers, self.rows, self.cols) != (layers, ROWS, COLS) {
+            return Err(format!(
+                "Backup is for {} layers of {}x{} keys, but the keyboard has {} layers of {}x{}",
+                self.layers, self.rows, self.cols, layers, ROWS, COLS
+            ));
+        }
+        if self.encoders.len() != layers as usize * ENCODERS as usize {
+            return Err(format!(
+                "Backup has {} encoder actions, but the keyboard has {} layers of {} encoders",
+                self.encoders.len(),
+                layers,
+                ENCODERS
+            ));
+        }
+        let macro_buffer_size = keyboard.macro_buffer_size()?;
+        if self.macros.len() > macro_buffer_size {
+            return Err(format!(
+                "Backup has {} bytes of macros, but the keyboard only has room for {}",
+                self.macros.len(),
+                macro_buffer_size
+            ));
+        }
+        let [tap_dance_count, combo_count, fork_count] = keyboard.entry_counts()?;
+        check_room("combos", self.combos.len(), combo_count)?;
+        check_room("tap dances", self.tap_dances.len(), tap_dance_count)?;
+        check_room("forks", self.forks.len(), fork_count)?;
+
+        keyboard.write_buffer(ID_DYNAMIC_KEYMAP_SET_BUFFER, &self.keymap)?;
+        keyboard.write_encoders(&self.encoders)?;
+        keyboard.write_buffer(ID_DYNAMIC_KEYMAP_MACRO_SET_BUFFER, &self.macros)?;
+        keyboard.write_entries(DYNAMIC_VIAL_COMBO_SET, &self.combos)?;
+        keyboard.write_entries(DYNAMIC_VIAL_TAP_DANCE_SET, &self.tap_dances)?;
+        keyboard.write_entries(DYNAMIC_VIAL_KEY_OVERRIDE_SET, &self.forks)?;
+        keyboard.write_settings(&self.settings)
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(BACKUP_MAGIC);
+        bytes.extend_from_slice(&[BACKUP_VERSION, self.layers, self.rows, self.cols]);
+        bytes.extend_from_slice(&(self.keymap.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.keymap);
+        bytes.extend_from_slice(&(self.macros.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.macros);
+        push_array_list(&mut bytes, "combos", &self.combos)?;
+        push_array_list(&mut bytes, "encoder actions", &self.encoders)?;
+        push_array_list(&mut bytes, "tap dances", &self.tap_dances)?;
+        push_array_list(&mut bytes, "forks", &self.forks)?;
+        bytes.push(list_len("settings", self.settings.len())?);
+        for (id, value) in &self.settings {
+            bytes.extend_from_slice(&id.to_le_bytes());
+            bytes.extend_from_slice(value);
+        }
+        Ok(bytes)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader(bytes);
+        if reader.take(4)? != BACKUP_MAGIC {
+            return Err("Not a Lily58 backup".into());
+        }
+        let version = reader.take(1)?[0];
+        if version != BACKUP_VERSION {
+            return Err(format!("Unsupported backup version {version}"));
+        }
+        let [layers, rows, cols] = reader.take(3)?.try_into().unwrap();
+        let keymap_len = reader.take_u32()? as usize;
+        let keymap = reader.take(keymap_len)?.to_vec();
+        let macros_len = reader.take_u32()? as usize;
+        let macros = reader.take(macros_len)?.to_vec();
+        let combos = reader.take_array_list()?;
+        let encoders = reader.take_array_list()?;
+        let tap_dances = reader.take_array_list()?;
+        let forks = reader.take_array_list()?;
+        let setting_count = reader.take(1)?[0];
+        let settings = (0..setting_count)
+            .map(|_| {
+                let id = u16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+                Ok((id, reader.take(SETTING_SIZE)?.try_into().unwrap()))
+            })
+            .collect::<Result<_, String>>()?;
+        if !reader.0.is_empty() {
+            return Err("Backup has trailing data".into());
+        }
+        Ok(Self {
+            layers,
+            rows,
+            cols,
+            keymap,
+            encoders,
+            macros,
+            combos,
+            tap_dances,
+            forks,
+            settings,
+        })
+    }
+}
+
+/// Lists are stored with a count byte, so they can't be longer than 255 items.
+fn list_len(what: &str, len: usize) -> Result<u8, String> {
+    u8::try_from(len).map_err(|_| format!("Cannot save {len} {what}, a backup holds at most 255"))
+}
+
+/// Appends a list in the format `Reader::take_array_list` reads.
+fn push_array_list<const N: usize>(
+    bytes: &mut Vec<u8>,
+    what: &str,
+    items: &[[u8; N]],
+) -> Result<(), String> {
+    bytes.push(list_len(what, items.len())?);
+    items.iter().for_each(|item| bytes.extend_from_slice(item));
+    Ok(())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < len {
+            return Err("Backup is truncated".into());
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn take_u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// A count byte followed by that many `N` byte items.
+    fn take_array_list<const N: usize>(&mut self) -> Result<Vec<[u8; N]>, String> {
+        let count = self.take(1)?[0];
+        (0..count)
+            .map(|_| Ok(self.take(N)?.try_into().unwrap()))
+            .collect()
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    match args {
+        [command, path] if command == "dump" => {
+            let backup = Backup::download(&Keyboard::open()?)?;
+            fs::write(path, backup.to_bytes()?).map_err(|e| format!("Cannot write {path}: {e}"))?;
+            println!(
+                "Saved {} layers, {} bytes of macros, {} combos, {} tap dances, {} forks and {} settings to {path}",
+                backup.layers,
+                backup.macros.len(),
+                backup.combos.len(),
+                backup.tap_dances.len(),
+                backup.forks.len(),
+                backup.settings.len()
+            );
+        }
+        [command, path] if command == "restore" => {
+            let bytes = fs::read(path).map_err(|e| format!("Cannot read {path}: {e}"))?;
+            Backup::from_bytes(&bytes)?.upload(&Keyboard::open()?)?;
+            println!("Restored {path}");
+        }
+        _ => return Err("Usage: lily58-backup <dump|restore> <file>".into()),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backup() -> Backup {
+        Backup {
+            layers: 2,
+            rows: ROWS,
+            cols: COLS,
+            keymap: (0..2 * ROWS as usize * COLS as usize * 2)
+                .map(|i| i as u8)
+                .collect(),
+            encoders: vec![[0, 0x80, 0, 0x81], [0, 0x4B, 0, 0x4E], [0; 4], [0; 4]],
+            macros: b"hello\0world\0".to_vec(),
+            combos: vec![[1; ENTRY_SIZE], [2; ENTRY_SIZE]],
+            tap_dances: vec![[0x29, 0, 0x01, 0x52, 0, 0, 0x39, 0, 200, 0]],
+            forks: vec![[3; ENTRY_SIZE]],
+            settings: vec![(2, [200, 0, 0, 0]), (7, [1, 0, 0, 0])],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let backup = backup();
+        assert_eq!(Backup::from_bytes(&backup.to_bytes().unwrap()), Ok(backup));
+    }
+
+    #[test]
+    fn round_trip_empty() {
+        let backup = Backup {
+            layers: 0,
+            rows: ROWS,
+            cols: COLS,
+            keymap: Vec::new(),
+            encoders: Vec::new(),
+            macros: Vec::new(),
+            combos: Vec::new(),
+            tap_dances: Vec::new(),
+            forks: Vec::new(),
+            settings: Vec::new(),
+        };
+        assert_eq!(Backup::from_bytes(&backup.to_bytes().unwrap()), Ok(backup));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = backup().to_bytes().unwrap();
+        for len in 4..bytes.len() {
+            assert_eq!(
+                Backup::from_bytes(&bytes[..len]),
+                Err("Backup is truncated".into()),
+                "cut to {len} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut bytes = backup().to_bytes().unwrap();
+        bytes.push(0);
+        assert_eq!(
+            Backup::from_bytes(&bytes),
+            Err("Backup has trailing data".into())
+        );
+    }
+
+    #[test]
+    fn wrong_magic() {
+        let mut bytes = backup().to_bytes().unwrap();
+        bytes[0] = b'X';
+        assert_eq!(
+            Backup::from_bytes(&bytes),
+            Err("Not a Lily58 backup".into())
+        );
+        assert_eq!(Backup::from_bytes(b"L5"), Err("Backup is truncated".into()));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        for version in [0, BACKUP_VERSION + 1] {
+            let mut bytes = backup().to_bytes().unwrap();
+            bytes[4] = version;
+            assert_eq!(
+                Backup::from_bytes(&bytes),
+                Err(format!("Unsupported backup version {version}"))
+            );
+        }
+    }
+
+    #[test]
+    fn too_many_entries() {
+        let mut backup = backup();
+        backup.forks = vec![[0; ENTRY_SIZE]; 256];
+        assert_eq!(
+            backup.to_bytes(),
+            Err("Cannot save 256 forks, a backup holds at most 255".into())
+        );
+        backup.forks.truncate(255);
+        assert_eq!(Backup::from_bytes(&backup.to_bytes().unwrap()), Ok(backup));
+    }
+}